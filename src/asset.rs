@@ -0,0 +1,476 @@
+//! The [`Asset`] responder.
+use crate::cache::CachePolicy;
+#[cfg(feature = "compress")]
+use crate::compression::{CompressedBody, Compressor};
+use crate::conditional::Validators;
+use crate::encoding::{self, Encoding};
+use crate::range;
+use crate::source::{AssetReader, AssetSource, Metadata};
+use rocket::http::{ContentType, Header};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use rocket::tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use std::future::Future;
+#[cfg(feature = "compress")]
+use std::io::Cursor;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// An asset that can be returned from a route
+///
+/// Responses carry a strong `ETag` and a `Last-Modified` header, and conditional requests
+/// (`If-None-Match`, `If-Modified-Since`, `If-Match` and `If-Unmodified-Since`) are answered with
+/// a body-less `304 Not Modified` or `412 Precondition Failed` when appropriate.
+///
+/// When precompressed sidecars are available (or the asset can be compressed on the fly), the
+/// encoding matching the request's `Accept-Encoding` is served with a `Content-Encoding` header
+/// (keeping the original `Content-Type`), and `Vary: Accept-Encoding` is added.
+///
+/// Byte range requests are supported (`Accept-Ranges: bytes`): single ranges are answered with a
+/// `206 Partial Content`, multiple ranges with a `multipart/byteranges` body, and unsatisfiable
+/// ones with a `416 Range Not Satisfiable`. `If-Range` is validated against the `ETag` and
+/// `Last-Modified` headers.
+///
+/// The `Cache-Control` header comes from the configured policies, but can be overridden per
+/// response:
+/// ```rust,no_run
+/// # #[macro_use] extern crate rocket;
+/// # use rocket_assets_fairing::{Asset, Assets};
+/// # use std::time::Duration;
+/// #[get("/avatar.png")]
+/// async fn avatar(assets: &Assets) -> Option<Asset> {
+///     let asset = assets.open("avatar.png").await.ok()?;
+///     Some(asset.private().max_age(Duration::from_secs(60)))
+/// }
+/// ```
+pub struct Asset {
+    pub(crate) identity: Representation,
+    pub(crate) variants: Vec<(Encoding, Variant)>,
+    pub(crate) cache: CachePolicy,
+    pub(crate) headers: Vec<Header<'static>>,
+}
+
+impl Asset {
+    /// Sets the `max-age` directive (in whole seconds), keeping the other ones
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        let seconds = max_age.as_secs().min(u32::MAX as u64) as u32;
+        self.cache.max_age = Some(seconds);
+        self.cache.no_store = false;
+        self
+    }
+
+    /// Marks the asset as `immutable`, i.e. never changing for as long as it's fresh
+    pub fn immutable(mut self) -> Self {
+        self.cache.immutable = true;
+        self.cache.no_store = false;
+        self
+    }
+
+    /// Only allows private caches (e.g. the browser's, but not CDNs) to store the response
+    pub fn private(mut self) -> Self {
+        self.cache.private = true;
+        self.cache.public = false;
+        self.cache.s_maxage = None;
+        self
+    }
+
+    /// Forbids any cache from storing the response, replacing every other directive
+    pub fn no_store(mut self) -> Self {
+        self.cache = CachePolicy {
+            no_store: true,
+            ..CachePolicy::default()
+        };
+        self
+    }
+
+    /// Requires caches to revalidate the response before each use (e.g. for the entry point of
+    /// an application), replacing every other directive
+    pub fn no_cache(mut self) -> Self {
+        self.cache = CachePolicy {
+            no_cache: true,
+            ..CachePolicy::default()
+        };
+        self
+    }
+
+    /// Adds a header to the response, replacing the one it would have had otherwise (including
+    /// `Cache-Control`)
+    pub fn header(mut self, header: impl Into<Header<'static>>) -> Self {
+        self.headers.push(header.into());
+        self
+    }
+
+    /// Overrides the content type, which is otherwise guessed from the extension
+    pub fn content_type(mut self, content_type: ContentType) -> Self {
+        self.identity.content_type = Some(content_type);
+        self
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Asset {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let Asset {
+            identity,
+            variants,
+            cache,
+            headers,
+        } = self;
+
+        let content_type = identity.content_type.clone();
+        let vary = !variants.is_empty();
+        // Ranges can't be served out of on the fly compression, prefer another representation
+        let ranged = req.headers().contains("Range");
+
+        let offered = variants
+            .iter()
+            .filter(|(_, variant)| !(ranged && variant.compressed()))
+            .map(|(e, _)| *e);
+        let encoding = encoding::negotiate(req, offered);
+        let variant = encoding.and_then(|encoding| {
+            let mut variants = variants.into_iter();
+            variants.find(|(e, _)| *e == encoding)
+        });
+
+        let (encoding, validators, body) = match variant {
+            None => {
+                let body = Body::Contents(identity.contents, identity.len);
+                (None, identity.validators, body)
+            }
+            Some((
+                encoding,
+                Variant::Sidecar {
+                    source,
+                    path,
+                    metadata,
+                },
+            )) => {
+                let contents = Opening::new(async move { source.open(&path).await });
+                let body = Body::Contents(Box::new(contents), metadata.len);
+                (Some(encoding), Validators::new(&metadata), body)
+            }
+            #[cfg(feature = "compress")]
+            Some((
+                encoding,
+                Variant::Compressed {
+                    compressor,
+                    source,
+                    path,
+                },
+            )) => {
+                let validators = identity.validators.encoded(encoding);
+                let version = identity.version;
+                let body = Body::Compressed(compressor, source, path, version);
+                (Some(encoding), validators, body)
+            }
+        };
+
+        let mut response = match validators.evaluate(req) {
+            Some(status) => Response::build().status(status).finalize(),
+            None => match body {
+                Body::Contents(contents, len) => match range::requested(req, &validators, len) {
+                    Some(ranges) => range::respond(contents, len, ranges, content_type),
+                    None => {
+                        let mut response = Response::new();
+                        response.set_sized_body(len as usize, contents);
+                        response.set_raw_header("Accept-Ranges", "bytes");
+                        if let Some(content_type) = content_type {
+                            response.set_header(content_type);
+                        }
+                        response
+                    }
+                },
+                #[cfg(feature = "compress")]
+                Body::Compressed(compressor, source, path, version) => {
+                    let encoding = encoding.expect("compressed variants have an encoding");
+
+                    let mut response = Response::new();
+                    match compressor.cached(&path, &version, encoding) {
+                        Some(data) => response.set_sized_body(data.len(), Cursor::new(data)),
+                        None => {
+                            let compression = compressor.compress(source, path, version, encoding);
+                            response.set_streamed_body(CompressedBody::new(compression))
+                        }
+                    }
+                    if let Some(content_type) = content_type {
+                        response.set_header(content_type);
+                    }
+                    response
+                }
+            },
+        };
+
+        if let Some(encoding) = encoding {
+            response.set_raw_header("Content-Encoding", encoding.name());
+        }
+        if vary {
+            response.set_raw_header("Vary", "Accept-Encoding");
+        }
+        if let Some(cache_control) = cache.header() {
+            response.set_raw_header("Cache-control", cache_control);
+        }
+        if let Some(last_modified) = validators.last_modified_header() {
+            response.set_raw_header("Last-Modified", last_modified);
+        }
+        response.set_raw_header("ETag", validators.etag);
+        for header in headers {
+            response.set_header(header);
+        }
+        Ok(response)
+    }
+}
+
+/// An encoded alternative to the original file
+pub(crate) enum Variant {
+    /// A precompressed sidecar file, only opened once it's picked
+    Sidecar {
+        source: Arc<dyn AssetSource>,
+        path: String,
+        metadata: Box<Metadata>,
+    },
+    /// Compressed on the fly (or cached) from the original file
+    #[cfg(feature = "compress")]
+    Compressed {
+        compressor: Arc<Compressor>,
+        source: Arc<dyn AssetSource>,
+        path: String,
+    },
+}
+
+impl Variant {
+    /// Whether the variant is compressed on the fly, and so can't serve ranges
+    fn compressed(&self) -> bool {
+        match self {
+            Variant::Sidecar { .. } => false,
+            #[cfg(feature = "compress")]
+            Variant::Compressed { .. } => true,
+        }
+    }
+}
+
+enum Body {
+    /// Stored contents, of the given length
+    Contents(Box<dyn AssetReader>, u64),
+    /// The original asset (with its version), to be compressed on the fly
+    #[cfg(feature = "compress")]
+    Compressed(Arc<Compressor>, Arc<dyn AssetSource>, String, String),
+}
+
+type Open = Pin<Box<dyn Future<Output = io::Result<Box<dyn AssetReader>>> + Send>>;
+
+/// A reader opening its asset when first read from (or seeked)
+struct Opening {
+    open: Option<Open>,
+    reader: Option<Box<dyn AssetReader>>,
+    /// Seek started before the asset was opened
+    seek: Option<SeekFrom>,
+}
+
+impl Opening {
+    fn new<F>(open: F) -> Self
+    where
+        F: Future<Output = io::Result<Box<dyn AssetReader>>> + Send + 'static,
+    {
+        Opening {
+            open: Some(Box::pin(open)),
+            reader: None,
+            seek: None,
+        }
+    }
+
+    fn poll_open(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<&mut Box<dyn AssetReader>>> {
+        if let Some(open) = self.open.as_mut() {
+            let mut reader = match open.as_mut().poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(reader) => reader?,
+            };
+            self.open = None;
+            if let Some(position) = self.seek.take() {
+                Pin::new(&mut reader).start_seek(position)?;
+            }
+            self.reader = Some(reader);
+        }
+        let reader = self.reader.as_mut().expect("assets are opened before use");
+        Poll::Ready(Ok(reader))
+    }
+}
+
+impl AsyncRead for Opening {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.poll_open(cx) {
+            Poll::Ready(Ok(reader)) => Pin::new(reader).poll_read(cx, buf),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl AsyncSeek for Opening {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> io::Result<()> {
+        match self.reader.as_mut() {
+            Some(reader) => Pin::new(reader).start_seek(position),
+            None => {
+                self.seek = Some(position);
+                Ok(())
+            }
+        }
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        match self.poll_open(cx) {
+            Poll::Ready(Ok(reader)) => Pin::new(reader).poll_complete(cx),
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// An opened asset, along with its validators
+pub(crate) struct Representation {
+    pub contents: Box<dyn AssetReader>,
+    pub content_type: Option<ContentType>,
+    pub validators: Validators,
+    /// Version of the contents, keying their compressions
+    #[cfg(feature = "compress")]
+    pub version: String,
+    pub len: u64,
+}
+
+impl Representation {
+    /// Opens an asset that was looked up in `source`
+    ///
+    /// Its content type is the one given by the source, see [`Mime`](crate::mime::Mime) for
+    /// guessing it otherwise.
+    pub async fn open(
+        source: &dyn AssetSource,
+        path: &str,
+        metadata: Metadata,
+    ) -> io::Result<Self> {
+        let contents = source.open(path).await?;
+        let validators = Validators::new(&metadata);
+        Ok(Representation {
+            contents,
+            content_type: metadata.content_type,
+            validators,
+            #[cfg(feature = "compress")]
+            version: metadata.version,
+            len: metadata.len,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Assets, MemorySource};
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+
+    #[rocket::get("/<customization>")]
+    async fn customized(assets: &Assets, customization: &str) -> Option<Asset> {
+        let asset = assets.open("style.css").await.ok()?;
+        let minute = Duration::from_secs(60);
+        Some(match customization {
+            "none" => asset,
+            "max_age" => asset.max_age(minute),
+            "immutable" => asset.immutable(),
+            "private" => asset.private(),
+            "no_store" => asset.no_store(),
+            "no_store_max_age" => asset.no_store().max_age(minute),
+            "no_cache" => asset.no_cache(),
+            "header" => asset.header(Header::new("Cache-Control", "no-transform")),
+            "header_private" => asset
+                .header(Header::new("Cache-Control", "no-transform"))
+                .private(),
+            "extra_header" => asset.header(Header::new("X-Frame-Options", "DENY")),
+            "content_type" => asset.content_type(ContentType::Plain),
+            _ => return None,
+        })
+    }
+
+    fn client() -> Client {
+        let config = Toml::string(
+            r#"
+            [[default.assets_cache]]
+            public = true
+            max_age = 3600
+            s_maxage = 600
+            "#,
+        );
+        let figment = rocket::Config::figment().merge(config.nested());
+        let mut source = MemorySource::new();
+        source.insert("style.css", "body {}");
+        let rocket = rocket::custom(figment)
+            .attach(Assets::fairing_from_source(source))
+            .mount("/", rocket::routes![customized]);
+        Client::tracked(rocket).unwrap()
+    }
+
+    fn cache_control(client: &Client, customization: &str) -> Vec<String> {
+        let response = client.get(format!("/{}", customization)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let values = response.headers().get("Cache-Control");
+        values.map(String::from).collect()
+    }
+
+    #[test]
+    fn cache_directives_can_be_overridden() {
+        let client = client();
+        let cases = [
+            ("none", "public, max-age=3600, s-maxage=600"),
+            ("max_age", "public, max-age=60, s-maxage=600"),
+            ("immutable", "public, max-age=3600, s-maxage=600, immutable"),
+            ("private", "private, max-age=3600"),
+            ("no_store", "no-store"),
+            ("no_store_max_age", "max-age=60"),
+            ("no_cache", "no-cache"),
+        ];
+        for (customization, expected) in cases {
+            assert_eq!(
+                cache_control(&client, customization),
+                [expected],
+                "{}",
+                customization
+            );
+        }
+    }
+
+    #[test]
+    fn headers_replace_the_defaults() {
+        let client = client();
+        assert_eq!(cache_control(&client, "header"), ["no-transform"]);
+        assert_eq!(cache_control(&client, "header_private"), ["no-transform"]);
+
+        let response = client.get("/extra_header").dispatch();
+        assert_eq!(response.headers().get_one("X-Frame-Options"), Some("DENY"));
+        assert_eq!(
+            response.headers().get_one("Cache-Control"),
+            Some("public, max-age=3600, s-maxage=600")
+        );
+    }
+
+    #[test]
+    fn content_type_can_be_overridden() {
+        let client = client();
+        let response = client.get("/none").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::CSS));
+
+        let response = client.get("/content_type").dispatch();
+        assert_eq!(response.content_type(), Some(ContentType::Plain));
+        assert_eq!(response.into_string().as_deref(), Some("body {}"));
+
+        let response = client
+            .get("/content_type")
+            .header(Header::new("Range", "bytes=0-3"))
+            .dispatch();
+        assert_eq!(response.status(), Status::PartialContent);
+        assert_eq!(response.content_type(), Some(ContentType::Plain));
+    }
+}
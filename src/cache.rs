@@ -0,0 +1,353 @@
+//! Cache policies (`Cache-Control`), and the rules picking them per asset.
+use crate::config;
+use crate::glob::Glob;
+use rocket::serde::Deserialize;
+use std::path::Path;
+use std::time::Duration;
+
+/// Max age used for fingerprinted assets (one year)
+const IMMUTABLE_MAX_AGE: u32 = 31536000;
+
+/// The directives of a `Cache-Control` header
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct CachePolicy {
+    /// `max-age`, in seconds
+    pub max_age: Option<u32>,
+    /// `s-maxage` (for shared caches), in seconds
+    pub s_maxage: Option<u32>,
+    pub public: bool,
+    pub private: bool,
+    pub no_cache: bool,
+    pub no_store: bool,
+    pub immutable: bool,
+    /// `stale-while-revalidate`, in seconds
+    pub stale_while_revalidate: Option<u32>,
+    /// `stale-if-error`, in seconds
+    pub stale_if_error: Option<u32>,
+}
+
+impl CachePolicy {
+    /// Just a `max-age`, as configured through `assets_max_age`
+    pub fn max_age(max_age: u32) -> Self {
+        CachePolicy {
+            max_age: Some(max_age),
+            ..CachePolicy::default()
+        }
+    }
+
+    /// The policy of fingerprinted assets, which never change
+    pub fn fingerprinted() -> Self {
+        CachePolicy {
+            max_age: Some(IMMUTABLE_MAX_AGE),
+            public: true,
+            immutable: true,
+            ..CachePolicy::default()
+        }
+    }
+
+    /// Formats the `Cache-Control` header value, `None` if there are no directives
+    pub fn header(&self) -> Option<String> {
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+        ];
+        let mut directives = flags
+            .iter()
+            .filter(|(set, _)| *set)
+            .map(|(_, directive)| directive.to_string())
+            .collect::<Vec<_>>();
+
+        let seconds = [(self.max_age, "max-age"), (self.s_maxage, "s-maxage")];
+        directives.extend(
+            seconds
+                .iter()
+                .filter_map(|(value, directive)| Some(format!("{}={}", directive, (*value)?))),
+        );
+        if self.immutable {
+            directives.push("immutable".to_string());
+        }
+        let stale = [
+            (self.stale_while_revalidate, "stale-while-revalidate"),
+            (self.stale_if_error, "stale-if-error"),
+        ];
+        directives.extend(
+            stale
+                .iter()
+                .filter_map(|(value, directive)| Some(format!("{}={}", directive, (*value)?))),
+        );
+
+        match directives.is_empty() {
+            true => None,
+            false => Some(directives.join(", ")),
+        }
+    }
+}
+
+/// A cache policy applied to the assets matching a glob pattern and/or extensions
+///
+/// Rules are configured as an ordered array (`[[default.assets_cache]]`), and the first one
+/// matching an asset is used. A rule without `match` nor `extensions` matches every asset.
+/// Unknown keys (e.g. a misspelled `max-age`) are errors, rather than silently ignored.
+///
+/// Rules can also be built in code, and added with [`AssetsConfig::cache_rule()`]:
+///
+/// ```rust
+/// use rocket_assets_fairing::{AssetsConfig, CacheRule};
+/// use std::time::Duration;
+///
+/// let config = AssetsConfig::new()
+///     .cache_rule(CacheRule::new().matching("*.html").no_cache())
+///     .cache_rule(CacheRule::new().extension("woff2").max_age(Duration::from_secs(604800)));
+/// ```
+///
+/// [`AssetsConfig::cache_rule()`]: crate::AssetsConfig::cache_rule
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(crate = "rocket::serde", from = "RawRule")]
+pub struct CacheRule {
+    /// Glob pattern matched against the relative path, e.g. `*.html` or `fonts/**`
+    pub(crate) pattern: Option<Glob>,
+    /// File extensions (case insensitive, without the dot)
+    pub(crate) extensions: Vec<String>,
+    pub(crate) policy: CachePolicy,
+}
+
+/// A cache rule as configured, with the policy's directives alongside its conditions
+#[derive(Default, Deserialize)]
+#[serde(crate = "rocket::serde", default, deny_unknown_fields)]
+struct RawRule {
+    #[serde(rename = "match")]
+    pattern: Option<Glob>,
+    extensions: Vec<String>,
+    #[serde(deserialize_with = "config::optional_seconds")]
+    max_age: Option<u32>,
+    #[serde(deserialize_with = "config::optional_seconds")]
+    s_maxage: Option<u32>,
+    public: bool,
+    private: bool,
+    no_cache: bool,
+    no_store: bool,
+    immutable: bool,
+    #[serde(deserialize_with = "config::optional_seconds")]
+    stale_while_revalidate: Option<u32>,
+    #[serde(deserialize_with = "config::optional_seconds")]
+    stale_if_error: Option<u32>,
+}
+
+impl From<RawRule> for CacheRule {
+    fn from(rule: RawRule) -> Self {
+        CacheRule {
+            pattern: rule.pattern,
+            extensions: rule.extensions,
+            policy: CachePolicy {
+                max_age: rule.max_age,
+                s_maxage: rule.s_maxage,
+                public: rule.public,
+                private: rule.private,
+                no_cache: rule.no_cache,
+                no_store: rule.no_store,
+                immutable: rule.immutable,
+                stale_while_revalidate: rule.stale_while_revalidate,
+                stale_if_error: rule.stale_if_error,
+            },
+        }
+    }
+}
+
+impl CacheRule {
+    /// A rule matching every asset, without any directive
+    pub fn new() -> Self {
+        CacheRule::default()
+    }
+
+    /// Only matches the paths matching a `.gitignore`-style pattern (e.g. `*.html` or `fonts/`)
+    pub fn matching(mut self, pattern: &str) -> Self {
+        self.pattern = Some(Glob::new(pattern));
+        self
+    }
+
+    /// Only matches the assets with the given extension, along with the ones previously given
+    pub fn extension(mut self, extension: &str) -> Self {
+        self.extensions.push(extension.to_string());
+        self
+    }
+
+    /// Sets the `max-age` directive (in whole seconds)
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.policy.max_age = Some(seconds(max_age));
+        self
+    }
+
+    /// Sets the `s-maxage` directive, for shared caches (in whole seconds)
+    pub fn s_maxage(mut self, s_maxage: Duration) -> Self {
+        self.policy.s_maxage = Some(seconds(s_maxage));
+        self
+    }
+
+    /// Adds the `public` directive
+    pub fn public(mut self) -> Self {
+        self.policy.public = true;
+        self
+    }
+
+    /// Adds the `private` directive
+    pub fn private(mut self) -> Self {
+        self.policy.private = true;
+        self
+    }
+
+    /// Adds the `no-cache` directive
+    pub fn no_cache(mut self) -> Self {
+        self.policy.no_cache = true;
+        self
+    }
+
+    /// Adds the `no-store` directive
+    pub fn no_store(mut self) -> Self {
+        self.policy.no_store = true;
+        self
+    }
+
+    /// Adds the `immutable` directive
+    pub fn immutable(mut self) -> Self {
+        self.policy.immutable = true;
+        self
+    }
+
+    /// Sets the `stale-while-revalidate` directive (in whole seconds)
+    pub fn stale_while_revalidate(mut self, stale: Duration) -> Self {
+        self.policy.stale_while_revalidate = Some(seconds(stale));
+        self
+    }
+
+    /// Sets the `stale-if-error` directive (in whole seconds)
+    pub fn stale_if_error(mut self, stale: Duration) -> Self {
+        self.policy.stale_if_error = Some(seconds(stale));
+        self
+    }
+
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        let extension = Path::new(path).extension().and_then(|e| e.to_str());
+        let extension_matches = self.extensions.is_empty()
+            || extension.is_some_and(|extension| {
+                self.extensions
+                    .iter()
+                    .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(extension))
+            });
+        extension_matches && self.pattern.as_ref().is_none_or(|glob| glob.matches(path))
+    }
+}
+
+fn seconds(duration: Duration) -> u32 {
+    duration.as_secs().min(u32::MAX as u64) as u32
+}
+
+/// Picks the policy of the first rule matching `path`
+pub(crate) fn policy<'a>(rules: &'a [CacheRule], path: &str) -> Option<&'a CachePolicy> {
+    rules
+        .iter()
+        .find(|rule| rule.matches(path))
+        .map(|rule| &rule.policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::figment::Figment;
+
+    fn rule(toml: &str) -> Result<CacheRule, String> {
+        let figment = Figment::from(Toml::string(toml));
+        figment.extract().map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn rules_match_patterns_and_extensions() {
+        let html = rule(r#"match = "*.html""#).unwrap();
+        assert!(html.matches("index.html"));
+        assert!(html.matches("docs/index.html"));
+        assert!(!html.matches("index.htm"));
+
+        let json = rule(r#"extensions = [".JSON", "xml"]"#).unwrap();
+        assert!(json.matches("data/feed.json"));
+        assert!(json.matches("feed.xml"));
+        assert!(!json.matches("json"));
+        assert!(!json.matches("feed.json.gz"));
+
+        let both = rule("match = \"api/\"\nextensions = [\"json\"]").unwrap();
+        assert!(both.matches("api/users.json"));
+        assert!(!both.matches("api/users.xml"));
+        assert!(!both.matches("users.json"));
+
+        let any = rule("").unwrap();
+        assert!(any.matches("anything"));
+
+        let rules = [html, json, any];
+        assert_eq!(policy(&rules, "feed.xml"), Some(&rules[1].policy));
+        assert_eq!(policy(&rules[..2], "app.js"), None);
+    }
+
+    #[test]
+    fn rules_parse_directives() {
+        let rule = rule(
+            r#"
+            max_age = "1d"
+            s_maxage = 60
+            public = true
+            stale_while_revalidate = "5m"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(rule.pattern, None);
+        assert_eq!(
+            rule.policy,
+            CachePolicy {
+                max_age: Some(86400),
+                s_maxage: Some(60),
+                public: true,
+                stale_while_revalidate: Some(300),
+                ..CachePolicy::default()
+            }
+        );
+    }
+
+    #[test]
+    fn rules_reject_unknown_keys() {
+        let error = rule("max-age = 60").unwrap_err();
+        assert!(error.contains("max-age"), "{}", error);
+        assert!(rule("pattern = \"*.html\"").is_err());
+    }
+
+    #[test]
+    fn policies_format_every_directive() {
+        assert_eq!(CachePolicy::default().header(), None);
+        assert_eq!(
+            CachePolicy::max_age(60).header().as_deref(),
+            Some("max-age=60")
+        );
+        assert_eq!(
+            CachePolicy::fingerprinted().header().as_deref(),
+            Some("public, max-age=31536000, immutable")
+        );
+
+        let policy = CachePolicy {
+            max_age: Some(60),
+            s_maxage: Some(120),
+            public: true,
+            private: true,
+            no_cache: true,
+            no_store: true,
+            immutable: true,
+            stale_while_revalidate: Some(30),
+            stale_if_error: Some(600),
+        };
+        assert_eq!(
+            policy.header().as_deref(),
+            Some(
+                "public, private, no-cache, no-store, max-age=60, s-maxage=120, immutable, \
+                 stale-while-revalidate=30, stale-if-error=600"
+            )
+        );
+    }
+}
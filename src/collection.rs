@@ -0,0 +1,277 @@
+//! Named asset collections, and request guards picking them.
+use crate::Assets;
+use rocket::figment::{value::Value, Figment};
+use rocket::http::Status;
+use rocket::outcome::IntoOutcome;
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Phase, Rocket};
+use std::collections::{BTreeMap, BTreeSet};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::OnceLock;
+
+/// Registry of the named collections, managed by the first named fairing to ignite
+///
+/// Collections are only added while igniting, so they are kept in an append-only list of slots,
+/// readable without locking.
+pub(crate) struct Collections {
+    /// Names of the collections configured under `assets`
+    configured: BTreeSet<String>,
+    slots: Slot,
+}
+
+/// A registered collection and its name, followed by the next slot
+#[derive(Default)]
+struct Slot {
+    collection: OnceLock<(String, Assets)>,
+    next: OnceLock<Box<Slot>>,
+}
+
+impl Collections {
+    /// Reads the names of the collections configured under `assets`, printing errors
+    pub fn configured(figment: &Figment) -> Result<Self, ()> {
+        let tables = match figment.extract_inner::<BTreeMap<String, Value>>("assets") {
+            Ok(tables) => tables,
+            Err(e) if e.missing() => BTreeMap::new(),
+            Err(e) => {
+                rocket::config::pretty_print_error(e);
+                return Err(());
+            }
+        };
+        Ok(Collections {
+            configured: tables.into_keys().collect(),
+            slots: Slot::default(),
+        })
+    }
+
+    /// Checks that a collection can be registered, before loading it
+    ///
+    /// Collections configured from Rocket's figment need an `[assets.<name>]` table, collections
+    /// configured in code (`in_code`) don't.
+    pub fn check(&self, name: &str, in_code: bool) -> Result<(), String> {
+        if !in_code && !self.configured.contains(name) {
+            return Err(format!(
+                "Asset collection '{}' is not configured (missing `[assets.{}]` table).",
+                name, name
+            ));
+        }
+        match self.get(name) {
+            Some(_) => Err(format!("Asset collection '{}' was attached twice.", name)),
+            None => Ok(()),
+        }
+    }
+
+    /// Stores the loaded collection in the first free slot
+    pub fn register(&self, name: &str, assets: Assets, in_code: bool) -> Result<(), String> {
+        self.check(name, in_code)?;
+        let mut collection = (name.to_string(), assets);
+        let mut slot = &self.slots;
+        loop {
+            collection = match slot.collection.set(collection) {
+                Ok(()) => return Ok(()),
+                Err(collection) => collection,
+            };
+            slot = slot.next.get_or_init(Box::default);
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&Assets> {
+        let mut loaded = self.loaded();
+        loaded.find_map(|(loaded, assets)| (loaded == name).then_some(assets))
+    }
+
+    /// Iterates over the collections loaded so far, by name
+    pub fn loaded(&self) -> impl Iterator<Item = (&str, &Assets)> {
+        let slots = std::iter::successors(Some(&self.slots), |slot| {
+            slot.next.get().map(|next| &**next)
+        });
+        slots.filter_map(|slot| {
+            let (name, assets) = slot.collection.get()?;
+            Some((name.as_str(), assets))
+        })
+    }
+}
+
+/// Finds a named collection, or the default one when `name` is `None`
+pub(crate) fn find<'a, P: Phase>(rocket: &'a Rocket<P>, name: Option<&str>) -> Option<&'a Assets> {
+    match name {
+        None => rocket.state::<Assets>(),
+        Some(name) => rocket.state::<Collections>()?.get(name),
+    }
+}
+
+/// Marker type naming an asset collection, to be used with the [`Named`] request guard
+///
+/// ```rust
+/// use rocket_assets_fairing::Collection;
+///
+/// struct Vendor;
+/// impl Collection for Vendor {
+///     const NAME: &'static str = "vendor";
+/// }
+/// ```
+pub trait Collection: Send + Sync + 'static {
+    /// Name of the collection, as given to [`Assets::fairing_named()`] or
+    /// [`Assets::fairing_named_with()`]
+    const NAME: &'static str;
+}
+
+/// Request guard for a named asset collection, dereferencing to its [`Assets`]
+///
+/// Like `&Assets`, the guard forwards with a `500 Internal Server Error` status if the collection
+/// isn't attached.
+///
+/// ```rust,no_run
+/// # #[macro_use] extern crate rocket;
+/// use rocket_assets_fairing::{Asset, Assets, Collection, Named};
+///
+/// struct Vendor;
+/// impl Collection for Vendor {
+///     const NAME: &'static str = "vendor";
+/// }
+///
+/// #[get("/jquery.js")]
+/// async fn jquery(vendor: Named<'_, Vendor>) -> Option<Asset> {
+///     vendor.open("jquery.js").await.ok()
+/// }
+///
+/// #[launch]
+/// fn rocket() -> _ {
+///     rocket::build()
+///         .attach(Assets::fairing_named(Vendor::NAME))
+///         .mount("/", routes![jquery])
+/// }
+/// ```
+pub struct Named<'r, C: Collection> {
+    assets: &'r Assets,
+    collection: PhantomData<fn() -> C>,
+}
+
+impl<'r, C: Collection> Deref for Named<'r, C> {
+    type Target = Assets;
+
+    fn deref(&self) -> &Assets {
+        self.assets
+    }
+}
+
+#[rocket::async_trait]
+impl<'r, C: Collection> FromRequest<'r> for Named<'r, C> {
+    type Error = ();
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        find(req.rocket(), Some(C::NAME))
+            .map(|assets| Named {
+                assets,
+                collection: PhantomData,
+            })
+            .or_forward(Status::InternalServerError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Asset, AssetsConfig};
+    use rocket::error::ErrorKind;
+    use rocket::local::blocking::Client;
+    use std::fs;
+    use tempfile::TempDir;
+
+    struct Vendor;
+    impl Collection for Vendor {
+        const NAME: &'static str = "vendor";
+    }
+
+    struct Fonts;
+    impl Collection for Fonts {
+        const NAME: &'static str = "fonts";
+    }
+
+    #[rocket::get("/vendor/<path>")]
+    async fn vendor(assets: Named<'_, Vendor>, path: &str) -> Option<Asset> {
+        assets.open(path).await.ok()
+    }
+
+    #[rocket::get("/fonts/<path>")]
+    async fn fonts(assets: Named<'_, Fonts>, path: &str) -> Option<Asset> {
+        assets.open(path).await.ok()
+    }
+
+    /// A directory holding a single file
+    fn dir(name: &str, contents: &str) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(name), contents).unwrap();
+        dir
+    }
+
+    #[test]
+    fn collections_are_picked_by_name() {
+        let vendor_dir = dir("lib.js", "vendor");
+        let fonts_dir = dir("lib.js", "fonts");
+        let figment = rocket::Config::figment()
+            .merge(("assets.vendor.dir", vendor_dir.path()))
+            .merge(("assets.fonts.dir", fonts_dir.path()));
+        let rocket = rocket::custom(figment)
+            .attach(Assets::fairing_named(Vendor::NAME))
+            .attach(Assets::fairing_named(Fonts::NAME))
+            .mount("/", rocket::routes![vendor, fonts]);
+        let client = Client::tracked(rocket).unwrap();
+
+        let response = client.get("/vendor/lib.js").dispatch();
+        assert_eq!(response.into_string().unwrap(), "vendor");
+        let response = client.get("/fonts/lib.js").dispatch();
+        assert_eq!(response.into_string().unwrap(), "fonts");
+
+        let rocket = client.rocket();
+        let names = rocket.state::<Collections>().unwrap().loaded();
+        let mut names = names.map(|(name, _)| name).collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["fonts", "vendor"]);
+        assert!(find(rocket, None).is_none());
+        assert!(find(rocket, Some("other")).is_none());
+    }
+
+    #[test]
+    fn missing_collections_forward() {
+        let vendor_dir = dir("lib.js", "vendor");
+        let config = AssetsConfig::new().dir(vendor_dir.path());
+        let rocket = rocket::build()
+            .attach(Assets::fairing_named_with(Vendor::NAME, config))
+            .mount("/", rocket::routes![vendor, fonts]);
+        let client = Client::tracked(rocket).unwrap();
+
+        assert_eq!(client.get("/vendor/lib.js").dispatch().status(), Status::Ok);
+        let response = client.get("/fonts/lib.js").dispatch();
+        assert_eq!(response.status(), Status::InternalServerError);
+    }
+
+    #[test]
+    fn collections_need_a_table_unless_configured_in_code() {
+        let figment = Figment::new().merge(("assets.fonts.dir", "fonts"));
+        let collections = Collections::configured(&figment).unwrap();
+        assert!(collections.check("fonts", false).is_ok());
+        let error = collections.check("vendor", false).unwrap_err();
+        assert!(
+            error.contains("missing `[assets.vendor]` table"),
+            "{}",
+            error
+        );
+        assert!(collections.check("vendor", true).is_ok());
+    }
+
+    #[test]
+    fn unconfigured_collections_fail_to_ignite() {
+        let rocket = rocket::build().attach(Assets::fairing_named(Vendor::NAME));
+        let error = Client::tracked(rocket).err().unwrap();
+        assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)));
+
+        // Configuring another collection doesn't help
+        let fonts_dir = dir("icons.woff2", "fonts");
+        let figment = rocket::Config::figment().merge(("assets.fonts.dir", fonts_dir.path()));
+        let rocket = rocket::custom(figment)
+            .attach(Assets::fairing_named(Fonts::NAME))
+            .attach(Assets::fairing_named(Vendor::NAME));
+        let error = Client::tracked(rocket).err().unwrap();
+        assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)));
+    }
+}
@@ -0,0 +1,399 @@
+//! On-the-fly compression of assets, with a size bounded (LRU) cache of the results.
+use crate::config::CompressionConfig;
+use crate::encoding::Encoding;
+use crate::source::{self, AssetSource};
+use rocket::http::ContentType;
+use rocket::tokio::io::{AsyncRead, ReadBuf};
+use rocket::tokio::sync::OnceCell;
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// Brotli quality used when compressing (0-11)
+const BROTLI_QUALITY: u32 = 6;
+/// Brotli window size (log2)
+const BROTLI_WINDOW: u32 = 22;
+
+/// Cache key: the compressed asset's path, its version and the encoding
+type Key = (String, String, Encoding);
+
+struct Slot {
+    data: Arc<OnceCell<Arc<[u8]>>>,
+    size: usize,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Cache {
+    slots: HashMap<Key, Slot>,
+    size: usize,
+    clock: u64,
+}
+
+impl Cache {
+    /// Returns the compressed data of `key` if it's ready, marking it as used
+    fn get(&mut self, key: &Key) -> Option<Arc<[u8]>> {
+        self.clock += 1;
+        let clock = self.clock;
+        let slot = self.slots.get_mut(key)?;
+        let data = slot.data.get()?.clone();
+        slot.last_used = clock;
+        Some(data)
+    }
+
+    /// Returns the (possibly not yet initialized) slot for `key`, marking it as used
+    fn slot(&mut self, key: &Key) -> Arc<OnceCell<Arc<[u8]>>> {
+        self.clock += 1;
+        let clock = self.clock;
+        let slot = self.slots.entry(key.clone()).or_insert_with(|| Slot {
+            data: Arc::default(),
+            size: 0,
+            last_used: clock,
+        });
+        slot.last_used = clock;
+        slot.data.clone()
+    }
+
+    /// Records the size of a freshly compressed entry, evicting the least recently used entries
+    /// until everything fits in `capacity` again
+    fn store(&mut self, key: &Key, size: usize, capacity: usize) {
+        if size > capacity {
+            self.slots.remove(key);
+            return;
+        }
+        if let Some(slot) = self.slots.get_mut(key) {
+            slot.size = size;
+            self.size += size;
+        }
+
+        while self.size > capacity {
+            let lru = self
+                .slots
+                .iter()
+                .filter(|(k, slot)| *k != key && slot.size > 0)
+                .min_by_key(|(_, slot)| slot.last_used)
+                .map(|(k, _)| k.clone());
+            match lru.and_then(|k| self.slots.remove(&k)) {
+                Some(slot) => self.size -= slot.size,
+                None => break,
+            }
+        }
+    }
+}
+
+/// Compresses assets on demand, sharing the results (and in-flight work) between requests
+pub(crate) struct Compressor {
+    config: CompressionConfig,
+    cache: Mutex<Cache>,
+}
+
+impl Compressor {
+    pub fn new(config: CompressionConfig) -> Self {
+        Compressor {
+            config,
+            cache: Mutex::default(),
+        }
+    }
+
+    /// Whether this encoding can be produced on the fly
+    pub fn supports(encoding: Encoding) -> bool {
+        matches!(encoding, Encoding::Brotli | Encoding::Gzip)
+    }
+
+    /// Whether an asset of this type and size is worth compressing
+    pub fn should_compress(&self, content_type: Option<&ContentType>, len: u64) -> bool {
+        len >= self.config.min_size.as_u64() && content_type.is_some_and(is_compressible)
+    }
+
+    /// Returns the compressed asset, if it's already cached
+    pub fn cached(&self, path: &str, version: &str, encoding: Encoding) -> Option<Arc<[u8]>> {
+        let key = (path.to_string(), version.to_string(), encoding);
+        let mut cache = self.cache.lock().expect("compression cache lock poisoned");
+        cache.get(&key)
+    }
+
+    /// Compresses the asset (or waits for a concurrent request already doing so), caching the
+    /// result
+    pub async fn compress(
+        self: Arc<Self>,
+        source: Arc<dyn AssetSource>,
+        path: String,
+        version: String,
+        encoding: Encoding,
+    ) -> io::Result<Arc<[u8]>> {
+        let key = (path, version, encoding);
+        let cell = self
+            .cache
+            .lock()
+            .expect("compression cache lock poisoned")
+            .slot(&key);
+        let _pending = Pending {
+            compressor: &self,
+            key: &key,
+            cell: &cell,
+        };
+
+        let data = cell
+            .get_or_try_init(|| async {
+                let contents = source::read(&*source, &key.0).await?;
+                let compressed: Arc<[u8]> =
+                    rocket::tokio::task::spawn_blocking(move || encode(&contents, encoding))
+                        .await
+                        .map_err(io::Error::from)??
+                        .into();
+
+                let capacity = self.config.cache_size.as_u64() as usize;
+                self.cache
+                    .lock()
+                    .expect("compression cache lock poisoned")
+                    .store(&key, compressed.len(), capacity);
+                Ok::<_, io::Error>(compressed)
+            })
+            .await;
+        data.cloned()
+    }
+
+    /// Drops every cached (or in-flight) compression of an asset
+    #[cfg(feature = "watch")]
+    pub fn invalidate(&self, path: &str) {
+        let mut cache = self.cache.lock().expect("compression cache lock poisoned");
+        let mut freed = 0;
+        cache.slots.retain(|(cached, _, _), slot| {
+            let stale = cached == path;
+            if stale {
+                freed += slot.size;
+            }
+            !stale
+        });
+        cache.size -= freed;
+    }
+}
+
+/// Removes the slot of a compression that didn't complete when dropped, whether it failed or its
+/// response was dropped midway, so that a later request compresses the asset again
+struct Pending<'a> {
+    compressor: &'a Compressor,
+    key: &'a Key,
+    cell: &'a Arc<OnceCell<Arc<[u8]>>>,
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if self.cell.initialized() {
+            return;
+        }
+        // Not panicking again if the lock was poisoned by a panic dropping this guard
+        let mut cache = match self.compressor.cache.lock() {
+            Ok(cache) => cache,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        // Leave the slot alone if it was replaced since, or if other requests are waiting on it
+        // (one of them carries on compressing)
+        let abandoned = cache.slots.get(self.key).is_some_and(|slot| {
+            Arc::ptr_eq(&slot.data, self.cell) && Arc::strong_count(self.cell) == 2
+        });
+        if abandoned {
+            cache.slots.remove(self.key);
+        }
+    }
+}
+
+/// Whether the (text-like) media type benefits from compression
+pub(crate) fn is_compressible(content_type: &ContentType) -> bool {
+    let (top, sub) = (content_type.top().as_str(), content_type.sub().as_str());
+    top.eq_ignore_ascii_case("text")
+        || sub.ends_with("+json")
+        || sub.ends_with("+xml")
+        || ["javascript", "json", "xml", "wasm", "svg+xml"]
+            .iter()
+            .any(|s| sub.eq_ignore_ascii_case(s))
+}
+
+pub(crate) fn encode(contents: &[u8], encoding: Encoding) -> io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(contents)?;
+            encoder.finish()
+        }
+        Encoding::Brotli => {
+            let mut encoder =
+                brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+            encoder.write_all(contents)?;
+            Ok(encoder.into_inner())
+        }
+        other => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} can't be compressed on the fly", other),
+        )),
+    }
+}
+
+type Compression = Pin<Box<dyn Future<Output = io::Result<Arc<[u8]>>> + Send>>;
+
+/// A response body that compresses the asset when first polled
+pub(crate) struct CompressedBody {
+    compression: Option<Compression>,
+    data: Arc<[u8]>,
+    position: usize,
+}
+
+impl CompressedBody {
+    pub fn new<F>(compression: F) -> Self
+    where
+        F: Future<Output = io::Result<Arc<[u8]>>> + Send + 'static,
+    {
+        CompressedBody {
+            compression: Some(Box::pin(compression)),
+            data: Arc::from(Vec::new()),
+            position: 0,
+        }
+    }
+}
+
+impl AsyncRead for CompressedBody {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if let Some(compression) = self.compression.as_mut() {
+            let data = match compression.as_mut().poll(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(data) => data,
+            };
+            self.compression = None;
+            self.data = data?;
+        }
+
+        let remaining = &self.data[self.position..];
+        let amount = remaining.len().min(buf.remaining());
+        buf.put_slice(&remaining[..amount]);
+        self.position += amount;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::{AssetReader, MemorySource, Metadata};
+    use crate::testing::Recording;
+    use rocket::futures::future::join_all;
+    use rocket::futures::FutureExt;
+
+    fn key(path: &str) -> Key {
+        (path.to_string(), "v1".to_string(), Encoding::Gzip)
+    }
+
+    /// Fills a slot as a finished compression would
+    fn insert(cache: &mut Cache, path: &str, size: usize, capacity: usize) {
+        let cell = cache.slot(&key(path));
+        cell.set(Arc::from(vec![0; size])).unwrap();
+        cache.store(&key(path), size, capacity);
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let mut cache = Cache::default();
+        insert(&mut cache, "a.css", 10, 30);
+        insert(&mut cache, "b.css", 10, 30);
+        insert(&mut cache, "c.css", 10, 30);
+        // A hit makes the oldest entry the most recently used one
+        assert!(cache.get(&key("a.css")).is_some());
+
+        insert(&mut cache, "d.css", 10, 30);
+        assert!(cache.slots.contains_key(&key("a.css")));
+        assert!(!cache.slots.contains_key(&key("b.css")));
+        assert_eq!(cache.size, 30);
+
+        // Evicts as many entries as needed, least recently used first
+        insert(&mut cache, "e.css", 20, 30);
+        assert_eq!(cache.slots.len(), 2);
+        assert!(cache.slots.contains_key(&key("d.css")));
+        assert!(cache.slots.contains_key(&key("e.css")));
+        assert_eq!(cache.size, 30);
+    }
+
+    #[test]
+    fn oversized_entries_are_not_kept() {
+        let mut cache = Cache::default();
+        insert(&mut cache, "a.css", 10, 30);
+        insert(&mut cache, "huge.css", 40, 30);
+        assert!(!cache.slots.contains_key(&key("huge.css")));
+        assert!(cache.slots.contains_key(&key("a.css")));
+        assert_eq!(cache.size, 10);
+    }
+
+    #[rocket::async_test]
+    async fn concurrent_requests_compress_once() {
+        let mut memory = MemorySource::new();
+        memory.insert("app.js", "console.log('hi');".repeat(100));
+        let source = Recording::new(memory);
+        let compressor = Arc::new(Compressor::new(CompressionConfig::default()));
+
+        let compressions = (0..8).map(|_| {
+            let source = Arc::new(source.clone()) as Arc<dyn AssetSource>;
+            let compressor = compressor.clone();
+            let path = "app.js".to_string();
+            rocket::tokio::spawn(compressor.compress(source, path, "v1".into(), Encoding::Gzip))
+        });
+        let results = join_all(compressions).await;
+        let first = results[0].as_ref().unwrap().as_ref().unwrap().clone();
+        for result in results {
+            assert_eq!(result.unwrap().unwrap(), first);
+        }
+        assert_eq!(source.opened(), ["app.js"]);
+
+        let cached = compressor.cached("app.js", "v1", Encoding::Gzip);
+        assert_eq!(cached, Some(first));
+    }
+
+    /// A source whose assets never finish opening
+    struct Stalled;
+
+    #[rocket::async_trait]
+    impl AssetSource for Stalled {
+        async fn metadata(&self, _: &str) -> io::Result<Metadata> {
+            Err(io::ErrorKind::NotFound.into())
+        }
+        async fn open(&self, _: &str) -> io::Result<Box<dyn AssetReader>> {
+            std::future::pending().await
+        }
+        async fn paths(&self) -> io::Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[rocket::async_test]
+    async fn incomplete_compressions_are_not_kept() {
+        let compressor = Arc::new(Compressor::new(CompressionConfig::default()));
+        let slots = |compressor: &Compressor| compressor.cache.lock().unwrap().slots.len();
+
+        // Dropped midway (e.g. along with its response)
+        let compression = compressor.clone().compress(
+            Arc::new(Stalled),
+            "app.js".into(),
+            "v1".into(),
+            Encoding::Gzip,
+        );
+        let mut compression = Box::pin(compression);
+        assert!(compression.as_mut().now_or_never().is_none());
+        assert_eq!(slots(&compressor), 1);
+        drop(compression);
+        assert_eq!(slots(&compressor), 0);
+
+        // Failed
+        let source = Arc::new(MemorySource::new());
+        let compression =
+            compressor
+                .clone()
+                .compress(source, "missing.js".into(), "v1".into(), Encoding::Gzip);
+        assert!(compression.await.is_err());
+        assert_eq!(slots(&compressor), 0);
+    }
+}
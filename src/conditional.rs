@@ -0,0 +1,267 @@
+//! Validators (`ETag`/`Last-Modified`) and evaluation of conditional request headers.
+#[cfg(feature = "compress")]
+use crate::encoding::Encoding;
+use crate::manifest::hex;
+use crate::source::Metadata;
+use rocket::http::{Method, Status};
+use rocket::Request;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Amount of digest bytes used in the entity tags of response bodies
+const ETAG_DIGEST_LEN: usize = 16;
+
+/// Validators identifying a specific version of an asset
+#[derive(Debug, Clone)]
+pub(crate) struct Validators {
+    /// Strong entity tag, including the surrounding quotes
+    pub etag: String,
+    /// Modification time, truncated to whole seconds (HTTP dates' resolution)
+    pub last_modified: Option<SystemTime>,
+}
+
+impl Validators {
+    /// Builds the validators of an asset from its metadata: the strong entity tag is its version
+    pub fn new(metadata: &Metadata) -> Self {
+        let etag = format!("\"{}\"", metadata.version);
+        // HTTP dates only have a precision of seconds
+        let last_modified = metadata
+            .modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime.as_secs()));
+
+        Validators {
+            etag,
+            last_modified,
+        }
+    }
+
+    /// Builds the validators of a response that has none from its (whole) body, and its
+    /// `Last-Modified` header if any
+    pub fn of_body(body: &[u8], last_modified: Option<&str>) -> Self {
+        let digest = Sha256::digest(body);
+        Validators {
+            etag: format!("\"{}\"", hex(&digest[..ETAG_DIGEST_LEN])),
+            last_modified: last_modified.and_then(parse_date),
+        }
+    }
+
+    /// Derives the validators of a representation encoded on the fly
+    #[cfg(feature = "compress")]
+    pub fn encoded(&self, encoding: Encoding) -> Self {
+        let tag = self.etag.trim_end_matches('"');
+        Validators {
+            etag: format!("{}-{}\"", tag, encoding.name()),
+            ..self.clone()
+        }
+    }
+
+    /// Formats the `Last-Modified` header value, if there's a modification time
+    pub fn last_modified_header(&self) -> Option<String> {
+        self.last_modified.map(httpdate::fmt_http_date)
+    }
+
+    /// Evaluates the request's preconditions (RFC 9110, section 13.2.2), returning the status to
+    /// respond with instead of the asset, if any
+    pub fn evaluate(&self, req: &Request<'_>) -> Option<Status> {
+        let headers = req.headers();
+        let safe = matches!(req.method(), Method::Get | Method::Head);
+
+        if let Some(if_match) = headers.get_one("If-Match") {
+            if !self.matches(if_match, true) {
+                return Some(Status::PreconditionFailed);
+            }
+        } else if let Some(since) = headers.get_one("If-Unmodified-Since") {
+            if let (Some(since), Some(modified)) = (parse_date(since), self.last_modified) {
+                if modified > since {
+                    return Some(Status::PreconditionFailed);
+                }
+            }
+        }
+
+        if let Some(if_none_match) = headers.get_one("If-None-Match") {
+            if self.matches(if_none_match, false) {
+                return Some(match safe {
+                    true => Status::NotModified,
+                    false => Status::PreconditionFailed,
+                });
+            }
+        } else if let Some(since) = headers.get_one("If-Modified-Since").filter(|_| safe) {
+            if let (Some(since), Some(modified)) = (parse_date(since), self.last_modified) {
+                if modified <= since {
+                    return Some(Status::NotModified);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Checks whether a list of entity tags (or `*`) matches ours
+    fn matches(&self, header: &str, strong: bool) -> bool {
+        if header.trim() == "*" {
+            return true;
+        }
+        entity_tags(header).any(|(weak, tag)| !(strong && weak) && tag == self.etag)
+    }
+}
+
+/// Iterates over a comma separated list of entity tags, yielding whether each is weak and the
+/// quoted tag itself
+fn entity_tags(header: &str) -> impl Iterator<Item = (bool, &str)> {
+    let mut rest = header;
+    std::iter::from_fn(move || loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        if rest.is_empty() {
+            return None;
+        }
+
+        let weak = rest.starts_with("W/");
+        let tag_start = if weak { &rest[2..] } else { rest };
+        if !tag_start.starts_with('"') {
+            // Malformed, skip to the next element
+            rest = tag_start.find(',').map_or("", |i| &tag_start[i..]);
+            continue;
+        }
+
+        let end = tag_start[1..].find('"').map_or(tag_start.len(), |i| i + 2);
+        let (tag, remaining) = tag_start.split_at(end);
+        rest = remaining;
+        return Some((weak, tag));
+    })
+}
+
+fn parse_date(value: &str) -> Option<SystemTime> {
+    httpdate::parse_http_date(value.trim()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Header;
+    use rocket::local::blocking::{Client, LocalRequest};
+
+    const MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+    const EARLIER: &str = "Wed, 21 Oct 2015 07:27:59 GMT";
+    const LATER: &str = "Wed, 21 Oct 2015 07:28:01 GMT";
+
+    fn validators() -> Validators {
+        Validators {
+            etag: "\"v1\"".into(),
+            last_modified: parse_date(MODIFIED),
+        }
+    }
+
+    fn evaluate(
+        request: LocalRequest<'_>,
+        headers: &[(&'static str, &'static str)],
+    ) -> Option<Status> {
+        let request = headers.iter().fold(request, |request, &(name, value)| {
+            request.header(Header::new(name, value))
+        });
+        validators().evaluate(request.inner())
+    }
+
+    #[test]
+    fn if_match() {
+        let client = Client::debug(rocket::build()).unwrap();
+        let get = |headers| evaluate(client.get("/"), headers);
+        assert_eq!(get(&[("If-Match", "\"v1\"")]), None);
+        assert_eq!(get(&[("If-Match", "\"v0\", \"v1\"")]), None);
+        assert_eq!(get(&[("If-Match", "*")]), None);
+        assert_eq!(
+            get(&[("If-Match", "\"v2\"")]),
+            Some(Status::PreconditionFailed)
+        );
+        // If-Match always uses the strong comparison
+        assert_eq!(
+            get(&[("If-Match", "W/\"v1\"")]),
+            Some(Status::PreconditionFailed)
+        );
+    }
+
+    #[test]
+    fn if_unmodified_since() {
+        let client = Client::debug(rocket::build()).unwrap();
+        let get = |headers| evaluate(client.get("/"), headers);
+        assert_eq!(get(&[("If-Unmodified-Since", MODIFIED)]), None);
+        assert_eq!(get(&[("If-Unmodified-Since", LATER)]), None);
+        assert_eq!(get(&[("If-Unmodified-Since", "not a date")]), None);
+        assert_eq!(
+            get(&[("If-Unmodified-Since", EARLIER)]),
+            Some(Status::PreconditionFailed)
+        );
+        // Ignored along with If-Match
+        let headers = [("If-Match", "\"v1\""), ("If-Unmodified-Since", EARLIER)];
+        assert_eq!(get(&headers), None);
+    }
+
+    #[test]
+    fn if_none_match() {
+        let client = Client::debug(rocket::build()).unwrap();
+        let get = |headers| evaluate(client.get("/"), headers);
+        assert_eq!(
+            get(&[("If-None-Match", "\"v1\"")]),
+            Some(Status::NotModified)
+        );
+        // If-None-Match uses the weak comparison
+        assert_eq!(
+            get(&[("If-None-Match", "W/\"v1\"")]),
+            Some(Status::NotModified)
+        );
+        assert_eq!(get(&[("If-None-Match", "*")]), Some(Status::NotModified));
+        assert_eq!(get(&[("If-None-Match", "\"v2\"")]), None);
+        assert_eq!(
+            get(&[("If-None-Match", "\"v0\",W/\"v1\"")]),
+            Some(Status::NotModified)
+        );
+
+        let head = evaluate(client.head("/"), &[("If-None-Match", "\"v1\"")]);
+        assert_eq!(head, Some(Status::NotModified));
+        let post = evaluate(client.post("/"), &[("If-None-Match", "\"v1\"")]);
+        assert_eq!(post, Some(Status::PreconditionFailed));
+        let post = evaluate(client.post("/"), &[("If-None-Match", "\"v2\"")]);
+        assert_eq!(post, None);
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let client = Client::debug(rocket::build()).unwrap();
+        let get = |headers| evaluate(client.get("/"), headers);
+        assert_eq!(
+            get(&[("If-Modified-Since", MODIFIED)]),
+            Some(Status::NotModified)
+        );
+        assert_eq!(
+            get(&[("If-Modified-Since", LATER)]),
+            Some(Status::NotModified)
+        );
+        assert_eq!(get(&[("If-Modified-Since", EARLIER)]), None);
+        assert_eq!(get(&[("If-Modified-Since", "not a date")]), None);
+
+        // Ignored along with If-None-Match, and for unsafe methods
+        let headers = [("If-None-Match", "\"v2\""), ("If-Modified-Since", LATER)];
+        assert_eq!(get(&headers), None);
+        let post = evaluate(client.post("/"), &[("If-Modified-Since", LATER)]);
+        assert_eq!(post, None);
+    }
+
+    #[test]
+    fn parses_entity_tag_lists() {
+        let tags = |header| entity_tags(header).collect::<Vec<_>>();
+        assert_eq!(
+            tags(" \"a\" ,W/\"b\",, \"c,d\""),
+            [(false, "\"a\""), (true, "\"b\""), (false, "\"c,d\"")]
+        );
+        assert_eq!(tags("a, b, \"c\""), [(false, "\"c\"")]);
+        assert_eq!(tags("W/x, \"c\""), [(false, "\"c\"")]);
+        assert_eq!(tags("\"unterminated"), [(false, "\"unterminated")]);
+        assert_eq!(tags(""), []);
+
+        // Malformed elements never match
+        let validators = validators();
+        assert!(!validators.matches("v1", false));
+        assert!(!validators.matches("\"v1", false));
+        assert!(validators.matches("garbage, \"v1\"", true));
+    }
+}
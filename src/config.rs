@@ -0,0 +1,620 @@
+//! The typed configuration of an asset collection.
+use crate::cache::CacheRule;
+use crate::decorate;
+use crate::encoding::Encoding;
+use crate::glob::Glob;
+use crate::integrity::HashAlgorithm;
+use crate::mime::MimeTypes;
+use crate::types::{Disallowed, TypePattern, TypesConfig};
+use rocket::data::ByteUnit;
+use rocket::figment::value::magic::{Magic, RelativePathBuf};
+use rocket::figment::value::{Dict, Map, Value};
+use rocket::figment::{self, Figment, Metadata, Profile, Provider};
+use rocket::http::ContentType;
+use rocket::serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use rocket::serde::Deserialize;
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// `max-age` of assets matching no cache rule, unless configured (one day)
+const DEFAULT_MAX_AGE: u32 = 86400;
+/// Key the `assets_*` keys of the default collection are gathered under
+const DEFAULT_TABLE: &str = "__assets";
+
+/// Configuration of an asset collection
+///
+/// It's usually extracted from Rocket's configuration: the `assets_*` keys for the default
+/// collection, and `[assets.<name>]` tables for named ones. It can also be built in code (e.g. in
+/// tests), and attached with [`Assets::fairing_with()`](crate::Assets::fairing_with):
+/// ```rust,no_run
+/// # #[macro_use] extern crate rocket;
+/// use rocket_assets_fairing::{Assets, AssetsConfig};
+/// use std::time::Duration;
+///
+/// #[launch]
+/// fn rocket() -> _ {
+///     let config = AssetsConfig::new()
+///         .dir("static")
+///         .max_age(Duration::from_secs(3600))
+///         .compression(true);
+///     rocket::build().attach(Assets::fairing_with(config))
+/// }
+/// ```
+///
+/// Durations (`max_age`, and the ones of cache rules) are either a number of seconds or a
+/// human-readable string such as `"1d"`, `"30m"` or `"1h30m"` (with `s`, `m`, `h`, `d` and `w`
+/// units).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub struct AssetsConfig {
+    /// Directories by priority, `None` for the collection's default one
+    #[serde(deserialize_with = "dirs")]
+    pub(crate) dir: Option<Vec<PathBuf>>,
+    /// `max-age` of assets matching no cache rule, in seconds
+    #[serde(deserialize_with = "seconds")]
+    pub(crate) max_age: u32,
+    pub(crate) cache: Vec<CacheRule>,
+    pub(crate) encodings: Vec<Encoding>,
+    pub(crate) compression: CompressionConfig,
+    /// Whether dotfiles are served (besides `.well-known`)
+    pub(crate) dotfiles: bool,
+    /// Patterns of paths never served nor listed
+    pub(crate) ignore: Vec<Glob>,
+    pub(crate) types: TypesConfig,
+    /// Media types by extension, overriding the known ones
+    pub(crate) mime_types: MimeTypes,
+    /// Charset given to text types without one (none if empty)
+    pub(crate) charset: String,
+    /// Whether to sniff the type of assets without an extension
+    pub(crate) sniff: bool,
+    /// Algorithms of the integrity metadata
+    pub(crate) integrity: Vec<HashAlgorithm>,
+    /// Public URL assets are served under, `None` to detect it from the mounted handler
+    pub(crate) base_url: Option<String>,
+    /// Path prefixes of other handlers' responses the policies are applied to
+    pub(crate) decorate: Vec<String>,
+    /// Largest body of the decorated responses read to compute an entity tag
+    pub(crate) decorate_max_size: ByteUnit,
+    /// Whether to watch for changes, `None` to only do so in the debug profile
+    pub(crate) reload: Option<bool>,
+    pub(crate) live_reload: bool,
+}
+
+impl Default for AssetsConfig {
+    fn default() -> Self {
+        AssetsConfig {
+            dir: None,
+            max_age: DEFAULT_MAX_AGE,
+            cache: Vec::new(),
+            encodings: Encoding::DEFAULT.to_vec(),
+            compression: CompressionConfig::default(),
+            dotfiles: false,
+            ignore: Vec::new(),
+            types: TypesConfig::default(),
+            mime_types: MimeTypes::default(),
+            charset: "utf-8".to_string(),
+            sniff: true,
+            integrity: HashAlgorithm::DEFAULT.to_vec(),
+            base_url: None,
+            decorate: Vec::new(),
+            decorate_max_size: decorate::DEFAULT_MAX_SIZE,
+            reload: None,
+            live_reload: false,
+        }
+    }
+}
+
+/// The `assets_compression` configuration table
+#[derive(Debug, Clone, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub(crate) struct CompressionConfig {
+    /// Whether to compress assets on the fly (when no sidecar is available)
+    pub enabled: bool,
+    /// Maximum total size of the cached compressed assets
+    pub cache_size: ByteUnit,
+    /// Assets smaller than this are never compressed
+    pub min_size: ByteUnit,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig {
+            enabled: false,
+            cache_size: ByteUnit::Mebibyte(32),
+            min_size: ByteUnit::Byte(1024),
+        }
+    }
+}
+
+impl AssetsConfig {
+    /// The default configuration, serving `assets/` with a one day long cache policy
+    pub fn new() -> Self {
+        AssetsConfig::default()
+    }
+
+    /// Serves assets from a single directory
+    pub fn dir<P: Into<PathBuf>>(self, dir: P) -> Self {
+        self.dirs(Some(dir))
+    }
+
+    /// Serves assets from layered directories, the first ones overriding the others
+    pub fn dirs<I, P>(mut self, dirs: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.dir = Some(dirs.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Sets the `max-age` of assets matching no cache rule (in whole seconds)
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age.as_secs().min(u32::MAX as u64) as u32;
+        self
+    }
+
+    /// Adds a cache rule, tried after the previously added ones (see [`CacheRule`])
+    pub fn cache_rule(mut self, rule: CacheRule) -> Self {
+        self.cache.push(rule);
+        self
+    }
+
+    /// Sets the encodings precompressed sidecars may be served with, by priority
+    pub fn encodings<I: IntoIterator<Item = Encoding>>(mut self, encodings: I) -> Self {
+        self.encodings = encodings.into_iter().collect();
+        self
+    }
+
+    /// Enables compressing assets on the fly
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.compression.enabled = enabled;
+        self
+    }
+
+    /// Sets the total size of the compressed assets kept in memory (32 MiB by default)
+    pub fn compression_cache_size(mut self, size: ByteUnit) -> Self {
+        self.compression.cache_size = size;
+        self
+    }
+
+    /// Sets the size under which assets aren't compressed (1 KiB by default)
+    pub fn compression_min_size(mut self, size: ByteUnit) -> Self {
+        self.compression.min_size = size;
+        self
+    }
+
+    /// Serves dotfiles (e.g. `.env`), which are hidden by default except for `.well-known`
+    pub fn dotfiles(mut self, enabled: bool) -> Self {
+        self.dotfiles = enabled;
+        self
+    }
+
+    /// Hides the paths matching a `.gitignore`-style pattern (e.g. `*.swp` or `drafts/`)
+    pub fn ignore(mut self, pattern: &str) -> Self {
+        self.ignore.push(Glob::new(pattern));
+        self
+    }
+
+    /// Only serves assets of the given extension (`png`) or media type (`image/png`, `image/*`),
+    /// along with the ones previously allowed
+    pub fn allow(mut self, pattern: &str) -> Self {
+        let allow = self.types.allow.get_or_insert_with(Vec::new);
+        allow.push(TypePattern::new(pattern));
+        self
+    }
+
+    /// Serves assets whose type isn't allowed as downloads (`application/octet-stream`
+    /// attachments), instead of refusing them
+    pub fn download_disallowed(mut self, enabled: bool) -> Self {
+        self.types.disallowed = match enabled {
+            true => Disallowed::Download,
+            false => Disallowed::Refuse,
+        };
+        self
+    }
+
+    /// Sandboxes documents able to run scripts (HTML, SVG and XML) with a
+    /// `Content-Security-Policy`, e.g. for collections of uploaded files
+    pub fn sandbox(mut self, enabled: bool) -> Self {
+        self.types.sandbox = enabled;
+        self
+    }
+
+    /// Serves the assets with the given extension (e.g. `webmanifest`) with a media type
+    pub fn mime_type(mut self, extension: &str, content_type: ContentType) -> Self {
+        self.mime_types.insert(extension, content_type);
+        self
+    }
+
+    /// Sets the charset given to text types without one (`utf-8` by default), `None` leaving
+    /// them without
+    pub fn charset(mut self, charset: Option<&str>) -> Self {
+        self.charset = charset.unwrap_or_default().to_string();
+        self
+    }
+
+    /// Whether to sniff the type of assets without an extension from their contents (`true` by
+    /// default)
+    pub fn sniff(mut self, enabled: bool) -> Self {
+        self.sniff = enabled;
+        self
+    }
+
+    /// Sets the algorithms of the integrity metadata (`sha384` by default), none disabling it
+    pub fn integrity<I: IntoIterator<Item = HashAlgorithm>>(mut self, algorithms: I) -> Self {
+        self.integrity = algorithms.into_iter().collect();
+        self
+    }
+
+    /// Sets the public URL assets are served under, either a path (`/static`) or an absolute URL
+    /// (`https://cdn.example.com/static`), instead of where [`Assets::routes()`] is mounted
+    ///
+    /// [`Assets::routes()`]: crate::Assets::routes
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = Some(base_url.to_string());
+        self
+    }
+
+    /// Applies the cache policy, sandboxing and entity tags to other handlers' successful responses
+    /// under a path prefix (e.g. `/static`, where a `FileServer` is mounted)
+    pub fn decorate(mut self, prefix: &str) -> Self {
+        self.decorate.push(prefix.to_string());
+        self
+    }
+
+    /// Sets the largest body of decorated responses read whole to compute their entity tag
+    /// (1 MiB by default), larger ones being left without one
+    pub fn decorate_max_size(mut self, size: ByteUnit) -> Self {
+        self.decorate_max_size = size;
+        self
+    }
+
+    /// Enables watching the directories for changes (the default in the debug profile)
+    pub fn reload(mut self, enabled: bool) -> Self {
+        self.reload = Some(enabled);
+        self
+    }
+
+    /// Enables live reloading (only honored in the debug profile)
+    pub fn live_reload(mut self, enabled: bool) -> Self {
+        self.live_reload = enabled;
+        self
+    }
+
+    /// Extracts the configuration of a collection, defaulting every missing key and printing
+    /// errors
+    pub(crate) fn extract(figment: &Figment, name: Option<&str>) -> Result<Self, ()> {
+        let result = match name {
+            None => figment
+                .clone()
+                .merge(DefaultTable(figment))
+                .extract_inner(DEFAULT_TABLE)
+                .map_err(|e| locate(e, DEFAULT_TABLE, "assets_")),
+            Some(name) => {
+                let table = format!("assets.{}", name);
+                figment
+                    .extract_inner(&table)
+                    .map_err(|e| locate(e, &table, &format!("{}.", table)))
+            }
+        };
+        match result {
+            Ok(config) => Ok(config),
+            // Named collections without a table are reported once registered
+            Err(e) if e.missing() => Ok(AssetsConfig::default()),
+            Err(e) => {
+                rocket::config::pretty_print_error(e);
+                Err(())
+            }
+        }
+    }
+}
+
+/// Points (chained) errors at the keys as written in the configuration, `prefix` replacing the
+/// `table` they were extracted from
+///
+/// `extract_inner()` appends the table's path after the keys inside of it, and the path of
+/// directories goes through `RelativePathBuf`'s internals.
+fn locate(errors: figment::Error, table: &str, prefix: &str) -> figment::Error {
+    let table = table.split('.').map(String::from).collect::<Vec<_>>();
+    let mut errors = errors
+        .into_iter()
+        .map(|mut e| {
+            if e.path.ends_with(&table) {
+                let keys = e.path[..e.path.len() - table.len()]
+                    .iter()
+                    .filter(|key| *key != RelativePathBuf::FIELDS[1])
+                    .map(String::as_str)
+                    .collect::<Vec<_>>();
+                e.path = vec![format!("{}{}", prefix, keys.join("."))];
+            }
+            e
+        })
+        .collect::<Vec<_>>();
+    errors.reverse();
+    errors
+        .into_iter()
+        .reduce(|chain, e| chain.chain(e))
+        .expect("errors aren't empty")
+}
+
+/// Provider gathering the `assets_*` keys of the default collection into a table, keeping track
+/// of where each value comes from
+struct DefaultTable<'a>(&'a Figment);
+
+impl Provider for DefaultTable<'_> {
+    fn metadata(&self) -> Metadata {
+        Metadata::named("assets configuration")
+    }
+
+    fn data(&self) -> figment::Result<Map<Profile, Dict>> {
+        let keys = match self.0.find_value("")? {
+            Value::Dict(_, keys) => keys,
+            _ => Dict::new(),
+        };
+        let table = keys
+            .into_iter()
+            .filter_map(|(key, value)| Some((key.strip_prefix("assets_")?.to_string(), value)))
+            .collect::<Dict>();
+
+        let mut data = Dict::new();
+        data.insert(DEFAULT_TABLE.to_string(), table.into());
+        let mut profiles = Map::new();
+        profiles.insert(Profile::Global, data);
+        Ok(profiles)
+    }
+}
+
+/// Deserializes a duration, given in seconds or as a human-readable string (e.g. `"1d"`)
+pub(crate) fn seconds<'de, D: Deserializer<'de>>(de: D) -> Result<u32, D::Error> {
+    struct Seconds;
+
+    impl Visitor<'_> for Seconds {
+        type Value = u32;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a positive amount of seconds, or a duration such as \"1d\" or \"30m\"")
+        }
+
+        fn visit_u64<E: de::Error>(self, seconds: u64) -> Result<u32, E> {
+            u32::try_from(seconds)
+                .map_err(|_| E::invalid_value(de::Unexpected::Unsigned(seconds), &self))
+        }
+
+        fn visit_i64<E: de::Error>(self, seconds: i64) -> Result<u32, E> {
+            u32::try_from(seconds)
+                .map_err(|_| E::invalid_value(de::Unexpected::Signed(seconds), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, duration: &str) -> Result<u32, E> {
+            parse_duration(duration)
+                .ok_or_else(|| E::invalid_value(de::Unexpected::Str(duration), &self))
+        }
+    }
+
+    de.deserialize_any(Seconds)
+}
+
+/// Like [`seconds()`], for optional durations
+pub(crate) fn optional_seconds<'de, D: Deserializer<'de>>(de: D) -> Result<Option<u32>, D::Error> {
+    seconds(de).map(Some)
+}
+
+/// Parses a human-readable duration (e.g. `"1h30m"`) into seconds
+fn parse_duration(duration: &str) -> Option<u32> {
+    let duration = duration.trim();
+    if let Ok(seconds) = duration.parse() {
+        return Some(seconds);
+    }
+    if duration.is_empty() {
+        return None;
+    }
+
+    let mut total: u32 = 0;
+    let mut rest = duration;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let (amount, tail) = rest.split_at(digits);
+        let unit = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit);
+        let multiplier = match unit.trim() {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        let seconds = amount.parse::<u32>().ok()?.checked_mul(multiplier)?;
+        total = total.checked_add(seconds)?;
+        rest = tail.trim_start();
+    }
+    Some(total)
+}
+
+/// Deserializes either a single directory or a list of them, relative to the configuration file
+/// they're declared in
+fn dirs<'de, D: Deserializer<'de>>(de: D) -> Result<Option<Vec<PathBuf>>, D::Error> {
+    struct Dirs;
+
+    impl<'de> Visitor<'de> for Dirs {
+        type Value = Vec<PathBuf>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a directory, or a non-empty list of directories")
+        }
+
+        fn visit_str<E: de::Error>(self, dir: &str) -> Result<Self::Value, E> {
+            Ok(vec![PathBuf::from(dir)])
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut dirs = Vec::new();
+            while let Some(dir) = seq.next_element::<PathBuf>()? {
+                dirs.push(dir);
+            }
+            match dirs.is_empty() {
+                true => Err(de::Error::invalid_length(0, &self)),
+                false => Ok(dirs),
+            }
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let (metadata_field, path_field) =
+                (RelativePathBuf::FIELDS[0], RelativePathBuf::FIELDS[1]);
+            let mut config_file = None;
+            let mut dirs = None;
+            while let Some(key) = map.next_key::<String>()? {
+                if key == metadata_field {
+                    config_file = Some(map.next_value::<PathBuf>()?);
+                } else if key == path_field {
+                    dirs = Some(map.next_value_seed(DirsSeed)?);
+                } else {
+                    map.next_value::<de::IgnoredAny>()?;
+                }
+            }
+            let dirs = dirs.ok_or_else(|| de::Error::missing_field("dir"))?;
+
+            let root = config_file.as_deref().and_then(Path::parent);
+            Ok(dirs
+                .into_iter()
+                .map(|dir| match root {
+                    Some(root) if dir.is_relative() => root.join(dir),
+                    _ => dir,
+                })
+                .collect())
+        }
+    }
+
+    struct DirsSeed;
+
+    impl<'de> de::DeserializeSeed<'de> for DirsSeed {
+        type Value = Vec<PathBuf>;
+
+        fn deserialize<D: Deserializer<'de>>(self, de: D) -> Result<Self::Value, D::Error> {
+            de.deserialize_any(Dirs)
+        }
+    }
+
+    // Going through `RelativePathBuf`'s magic provides the path of the configuration file
+    let dirs = de.deserialize_struct(RelativePathBuf::NAME, RelativePathBuf::FIELDS, Dirs)?;
+    Ok(Some(dirs))
+}
+
+/// Checks a base URL, either an absolute path or an `http(s)` URL, trimming its trailing `/`
+pub(crate) fn base_url(url: &str) -> Result<String, &'static str> {
+    let path = match url.split_once("://") {
+        Some((scheme, rest)) if scheme == "http" || scheme == "https" => {
+            let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            if authority.is_empty() {
+                return Err("it has no host");
+            }
+            path
+        }
+        Some(_) => return Err("only http and https URLs are supported"),
+        None if url.starts_with('/') && !url.starts_with("//") => url,
+        None => return Err("it must be an absolute path or URL"),
+    };
+    if path.contains(['?', '#']) {
+        return Err("it can't have a query or fragment");
+    }
+    Ok(url.trim_end_matches('/').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::figment::providers::{Format, Toml};
+
+    fn extract(toml: &str, name: Option<&str>) -> Result<AssetsConfig, ()> {
+        let figment = Figment::from(Toml::string(toml).nested());
+        AssetsConfig::extract(&figment, name)
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("30m"), Some(30 * 60));
+        assert_eq!(parse_duration("1d"), Some(86400));
+        assert_eq!(parse_duration("1h 30m"), Some(5400));
+        assert_eq!(parse_duration("2w"), Some(2 * 7 * 86400));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("1y"), None);
+        assert_eq!(parse_duration("1d30"), None);
+        assert_eq!(parse_duration("-1d"), None);
+        assert_eq!(parse_duration("100000w"), None);
+    }
+
+    #[test]
+    fn default_collection_keys_are_gathered() {
+        let config = extract(
+            r#"
+            [default]
+            assets_dir = ["overrides", "/srv/assets"]
+            assets_max_age = "1h"
+            assets_reload = false
+            assets_decorate_max_size = "512 KiB"
+            "#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            config.dir,
+            Some(vec![
+                PathBuf::from("overrides"),
+                PathBuf::from("/srv/assets")
+            ])
+        );
+        assert_eq!(config.max_age, 3600);
+        assert_eq!(config.reload, Some(false));
+        assert_eq!(config.decorate_max_size, ByteUnit::Kibibyte(512));
+
+        let config = extract("", None).unwrap();
+        assert_eq!(config.dir, None);
+        assert_eq!(config.max_age, DEFAULT_MAX_AGE);
+    }
+
+    #[test]
+    fn named_collections_use_their_table() {
+        let toml = r#"
+            [default]
+            assets_max_age = 10
+            [default.assets.vendor]
+            dir = "vendor"
+            max_age = "30m"
+            "#;
+        let config = extract(toml, Some("vendor")).unwrap();
+        assert_eq!(config.dir, Some(vec![PathBuf::from("vendor")]));
+        assert_eq!(config.max_age, 1800);
+        assert_eq!(extract(toml, None).unwrap().max_age, 10);
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(extract("default.assets_max_age = -1", None).is_err());
+        assert!(extract("default.assets_max_age = \"soon\"", None).is_err());
+        assert!(extract("default.assets_dir = []", None).is_err());
+        assert!(extract("default.assets.vendor.max_age = 1.5", Some("vendor")).is_err());
+    }
+
+    #[test]
+    fn base_urls_are_checked() {
+        assert_eq!(base_url("/static/").as_deref(), Ok("/static"));
+        assert_eq!(base_url("/").as_deref(), Ok(""));
+        let cdn = base_url("https://cdn.example.com/static/");
+        assert_eq!(cdn.as_deref(), Ok("https://cdn.example.com/static"));
+        assert!(base_url("http://cdn.example.com").is_ok());
+        for invalid in [
+            "static",
+            "//cdn.example.com",
+            "ftp://cdn",
+            "https:///a",
+            "/a?v=1",
+        ] {
+            assert!(base_url(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,325 @@
+//! Applying a collection's policies to other handlers' responses (e.g. `FileServer`'s).
+use crate::cache::{self, CachePolicy};
+use crate::conditional::Validators;
+use crate::Assets;
+use rocket::data::ByteUnit;
+use rocket::http::{Method, Status};
+use rocket::response::Body;
+use rocket::{error_, Request, Response};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Mutex;
+
+/// Largest body read whole to compute an entity tag by default, larger ones are left without one
+pub(crate) const DEFAULT_MAX_SIZE: ByteUnit = ByteUnit::Mebibyte(1);
+
+/// How a collection decorates other handlers' responses
+pub(crate) struct Decorate {
+    /// Path prefixes, without trailing `/`s
+    pub prefixes: Vec<String>,
+    /// Largest body read whole to compute an entity tag
+    pub max_size: ByteUnit,
+    /// Tags of the bodies served with a `Last-Modified` header, by relative path
+    tags: Mutex<HashMap<String, Tagged>>,
+}
+
+/// An entity tag computed from a body, along with what identifies the body
+struct Tagged {
+    last_modified: String,
+    len: usize,
+    validators: Validators,
+}
+
+impl Decorate {
+    pub fn new(prefixes: Vec<String>, max_size: ByteUnit) -> Self {
+        Decorate {
+            prefixes,
+            max_size,
+            tags: Mutex::default(),
+        }
+    }
+
+    /// The validators computed for a body of this path, modification time and length
+    fn cached(&self, relative: &str, last_modified: &str, len: usize) -> Option<Validators> {
+        let tags = self.tags.lock().expect("tags lock poisoned");
+        let tagged = tags.get(relative)?;
+        let current = tagged.last_modified == last_modified && tagged.len == len;
+        current.then(|| tagged.validators.clone())
+    }
+
+    fn store(&self, relative: &str, last_modified: &str, len: usize, validators: &Validators) {
+        let tagged = Tagged {
+            last_modified: last_modified.to_string(),
+            len,
+            validators: validators.clone(),
+        };
+        let mut tags = self.tags.lock().expect("tags lock poisoned");
+        tags.insert(relative.to_string(), tagged);
+    }
+}
+
+/// Checks a path prefix (e.g. `/static`), trimming its trailing `/`
+pub(crate) fn prefix(prefix: &str) -> Result<String, &'static str> {
+    match prefix.starts_with('/') {
+        true => Ok(prefix.trim_end_matches('/').to_string()),
+        false => Err("it must be an absolute path"),
+    }
+}
+
+/// The path of a request relative to the first prefix it's under, if any
+fn relative(prefixes: &[String], req: &Request<'_>) -> Option<String> {
+    let segments = req.uri().path().segments().collect::<Vec<_>>();
+    prefixes.iter().find_map(|prefix| {
+        let mut rest = segments.iter();
+        for segment in prefix.split('/').filter(|s| !s.is_empty()) {
+            if *rest.next()? != segment {
+                return None;
+            }
+        }
+        Some(rest.copied().collect::<Vec<_>>().join("/"))
+    })
+}
+
+/// Adds the cache policy, sandboxing headers and validators of the collection to a successful
+/// response under one of its `decorate` prefixes, evaluating conditional requests
+///
+/// Headers the response already has are kept, so the collection's own assets are left as is, and
+/// so are responses already decorated by another collection.
+///
+/// Tagging costs reading (and hashing) the body whole, up to `max_size`. Bodies with a
+/// `Last-Modified` header are only hashed once per modification, those without on every request.
+pub(crate) async fn decorate<'r>(assets: &Assets, req: &'r Request<'_>, res: &mut Response<'r>) {
+    let safe = matches!(req.method(), Method::Get | Method::Head);
+    if !safe || res.status() != Status::Ok {
+        return;
+    }
+    let relative = match relative(&assets.decorate.prefixes, req) {
+        Some(relative) => relative,
+        None => return,
+    };
+
+    if !res.headers().contains("Cache-Control") {
+        let policy = cache::policy(&assets.cache_rules, &relative)
+            .cloned()
+            .unwrap_or_else(|| CachePolicy::max_age(assets.cache_max_age));
+        if let Some(cache_control) = policy.header() {
+            res.set_raw_header("Cache-Control", cache_control);
+        }
+    }
+    for header in assets.types.security_headers(res.content_type().as_ref()) {
+        if !res.headers().contains(header.name().as_str()) {
+            res.set_header(header);
+        }
+    }
+    if !res.headers().contains("ETag") {
+        tag(&assets.decorate, &relative, req, res).await;
+    }
+}
+
+/// Sets the entity tag of a response from its body, answering conditional requests
+async fn tag<'r>(
+    decorate: &Decorate,
+    relative: &str,
+    req: &'r Request<'_>,
+    res: &mut Response<'r>,
+) {
+    // Streamed (unsized) bodies are left untagged
+    let len = match res.body_mut().size().await {
+        Some(len) if len as u64 <= decorate.max_size.as_u64() => len,
+        _ => return,
+    };
+    let last_modified = res.headers().get_one("Last-Modified").map(String::from);
+    let cached = last_modified
+        .as_deref()
+        .and_then(|last_modified| decorate.cached(relative, last_modified, len));
+
+    let (validators, body) = match cached {
+        Some(validators) => (validators, None),
+        None => {
+            let body = match res.body_mut().to_bytes().await {
+                Ok(body) => body,
+                Err(e) => {
+                    error_!("Failed to read response body: {}.", e);
+                    res.set_status(Status::InternalServerError);
+                    *res.body_mut() = Body::default();
+                    return;
+                }
+            };
+            let validators = Validators::of_body(&body, last_modified.as_deref());
+            if let Some(last_modified) = &last_modified {
+                decorate.store(relative, last_modified, len, &validators);
+            }
+            (validators, Some(body))
+        }
+    };
+
+    res.set_raw_header("ETag", validators.etag.clone());
+    match validators.evaluate(req) {
+        Some(status) => {
+            res.set_status(status);
+            *res.body_mut() = Body::default();
+        }
+        // The body was taken out to be hashed
+        None => {
+            if let Some(body) = body {
+                res.set_sized_body(body.len(), Cursor::new(body));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Assets, AssetsConfig, MemorySource};
+    use rocket::data::ByteUnit;
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::fs::FileServer;
+    use rocket::http::{Header, Status};
+    use rocket::local::blocking::Client;
+    use rocket::State;
+    use std::fs;
+    use std::sync::Mutex;
+    use tempfile::TempDir;
+
+    #[rocket::get("/other")]
+    fn other() -> &'static str {
+        "other"
+    }
+
+    fn client() -> (Client, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("page.html"), "<script></script>").unwrap();
+        fs::write(dir.path().join("data.json"), "{}").unwrap();
+        let config = Toml::string(
+            r#"
+            [default]
+            assets_decorate = ["/static/", "/assets"]
+            assets_max_age = 60
+            assets_types = { sandbox = true }
+
+            [[default.assets_cache]]
+            match = "*.json"
+            no_cache = true
+            "#,
+        );
+        let figment = rocket::Config::figment().merge(config.nested());
+        let mut source = MemorySource::new();
+        source.insert("style.css", "body {}");
+        let rocket = rocket::custom(figment)
+            .attach(Assets::fairing_from_source(source))
+            .mount("/static", FileServer::from(dir.path()))
+            .mount("/assets", Assets::routes())
+            .mount("/", rocket::routes![other]);
+        (Client::tracked(rocket).unwrap(), dir)
+    }
+
+    #[test]
+    fn responses_under_prefixes_are_decorated() {
+        let (client, _dir) = client();
+        let response = client.get("/static/page.html").dispatch();
+        let headers = response.headers();
+        assert_eq!(headers.get_one("Cache-Control"), Some("max-age=60"));
+        assert!(headers.contains("Content-Security-Policy"));
+        let etag = headers.get_one("ETag").unwrap().to_string();
+        assert_eq!(response.into_string().as_deref(), Some("<script></script>"));
+
+        let response = client
+            .get("/static/page.html")
+            .header(Header::new("If-None-Match", etag))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+        assert_eq!(response.into_string(), None);
+
+        let response = client.get("/static/data.json").dispatch();
+        assert_eq!(
+            response.headers().get_one("Cache-Control"),
+            Some("no-cache")
+        );
+        assert!(!response.headers().contains("Content-Security-Policy"));
+    }
+
+    #[test]
+    fn other_responses_are_left_alone() {
+        let (client, _dir) = client();
+        let response = client.get("/other").dispatch();
+        assert!(!response.headers().contains("Cache-Control"));
+        assert!(!response.headers().contains("ETag"));
+
+        let response = client.get("/static/missing.html").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert!(!response.headers().contains("ETag"));
+
+        // Assets are already decorated
+        let response = client.get("/assets/style.css").dispatch();
+        assert_eq!(
+            response.headers().get_one("Cache-Control"),
+            Some("max-age=60")
+        );
+        assert_eq!(response.headers().get("ETag").count(), 1);
+    }
+
+    #[derive(rocket::Responder)]
+    struct Dated(String, Header<'static>);
+
+    #[rocket::get("/dated")]
+    fn dated(state: &State<Mutex<(&'static str, &'static str)>>) -> Dated {
+        let (body, last_modified) = *state.lock().unwrap();
+        Dated(
+            body.to_string(),
+            Header::new("Last-Modified", last_modified),
+        )
+    }
+
+    #[test]
+    fn tags_are_cached_by_modification_time() {
+        const MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("page.html"), "<p>Hello</p>").unwrap();
+        let config = AssetsConfig::new()
+            .dir(dir.path())
+            .decorate("/api")
+            .decorate("/static")
+            .decorate_max_size(ByteUnit::Byte(8));
+        let rocket = rocket::build()
+            .attach(Assets::fairing_with(config))
+            .manage(Mutex::new(("v1", MODIFIED)))
+            .mount("/static", FileServer::from(dir.path()))
+            .mount("/api", rocket::routes![dated]);
+        let client = Client::tracked(rocket).unwrap();
+        let etag = |client: &Client| {
+            let response = client.get("/api/dated").dispatch();
+            response.headers().get_one("ETag").unwrap().to_string()
+        };
+
+        let first = etag(&client);
+        let assets = client.rocket().state::<Assets>().unwrap();
+        let cached = assets.decorate.cached("dated", MODIFIED, 2).unwrap();
+        assert_eq!(cached.etag, first);
+        assert_eq!(etag(&client), first);
+        let response = client
+            .get("/api/dated")
+            .header(Header::new("If-None-Match", first.clone()))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotModified);
+
+        let state = client.rocket().state::<Mutex<(&str, &str)>>().unwrap();
+        *state.lock().unwrap() = ("v2", "Wed, 21 Oct 2015 07:28:01 GMT");
+        assert_ne!(etag(&client), first);
+
+        // Larger bodies aren't read
+        let response = client.get("/static/page.html").dispatch();
+        assert!(!response.headers().contains("ETag"));
+        assert_eq!(response.into_string().as_deref(), Some("<p>Hello</p>"));
+    }
+
+    #[test]
+    fn prefixes_must_be_absolute() {
+        let config = AssetsConfig::new().decorate("static");
+        let rocket = rocket::build().attach(Assets::fairing_with(config));
+        let error = Client::tracked(rocket).err().unwrap();
+        assert!(matches!(
+            error.kind(),
+            rocket::error::ErrorKind::FailedFairings(_)
+        ));
+    }
+}
@@ -0,0 +1,464 @@
+//! Assets embedded in the executable, for single binary deployments.
+//!
+//! Files are read, hashed and (optionally) compressed by a build script, using [`Builder`], and
+//! embedded with [`include_assets!`]. They're then served through the same [`Assets`] API, so
+//! only the attached fairing changes: [`Assets::fairing_embedded()`] instead of
+//! [`Assets::fairing()`].
+//!
+//! ```toml
+//! [features]
+//! embed = ["rocket-assets-fairing/embed"]
+//!
+//! [build-dependencies]
+//! rocket-assets-fairing = { version = "0.1", features = ["embed"] }
+//! ```
+//!
+//! ```rust,ignore
+//! // build.rs
+//! fn main() {
+//!     #[cfg(feature = "embed")]
+//!     rocket_assets_fairing::embed::Builder::new("assets")
+//!         .build()
+//!         .expect("failed to embed assets");
+//! }
+//! ```
+//!
+//! ```rust,ignore
+//! // main.rs
+//! #[launch]
+//! fn rocket() -> _ {
+//!     #[cfg(feature = "embed")]
+//!     let assets = Assets::fairing_embedded(rocket_assets_fairing::include_assets!());
+//!     #[cfg(not(feature = "embed"))]
+//!     let assets = Assets::fairing();
+//!
+//!     rocket::build().attach(assets).mount("/assets", Assets::routes())
+//! }
+//! ```
+//!
+//! [`Assets`]: crate::Assets
+//! [`Assets::fairing()`]: crate::Assets::fairing
+//! [`Assets::fairing_embedded()`]: crate::Assets::fairing_embedded
+//! [`include_assets!`]: crate::include_assets
+#[cfg(feature = "compress")]
+use crate::compression::{self, Compressor};
+use crate::filter::Filter;
+use crate::glob::Glob;
+use crate::manifest;
+use crate::mime;
+use crate::source::{self, AssetReader, AssetSource, Metadata};
+use crate::Encoding;
+use rocket::http::ContentType;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Name of the file generated in `OUT_DIR`
+const GENERATED: &str = "rocket_assets.rs";
+/// Assets smaller than this aren't compressed at build time
+#[cfg(feature = "compress")]
+const MIN_COMPRESSED_SIZE: usize = 1024;
+
+/// Includes the assets embedded by [`Builder::build()`] in the build script, as a
+/// `&'static [EmbeddedFile]` to be given to [`Assets::fairing_embedded()`]
+///
+/// [`Assets::fairing_embedded()`]: crate::Assets::fairing_embedded
+#[macro_export]
+macro_rules! include_assets {
+    () => {
+        include!(concat!(env!("OUT_DIR"), "/rocket_assets.rs"))
+    };
+}
+
+/// A file embedded in the executable, as generated by [`Builder`]
+#[derive(Debug)]
+pub struct EmbeddedFile {
+    /// Relative path, with `/` separators
+    pub path: &'static str,
+    /// Contents of the file
+    pub contents: &'static [u8],
+    /// Media type, guessed from the extension
+    pub content_type: Option<&'static str>,
+    /// SHA-256 digest of the contents
+    pub digest: [u8; 32],
+    /// Modification time, in seconds since the UNIX epoch
+    pub modified: Option<u64>,
+    /// Precompressed variants of the contents
+    pub encoded: &'static [(Encoding, &'static [u8])],
+}
+
+impl EmbeddedFile {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            len: self.contents.len() as u64,
+            modified: self
+                .modified
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+            version: manifest::hex(&self.digest[..16]),
+            content_type: self.content_type.and_then(ContentType::parse_flexible),
+        }
+    }
+}
+
+/// Files embedded in the executable by [`include_assets!`], by relative path
+///
+/// Precompressed variants are served as (virtual) sidecars: `style.css.br` for the brotli
+/// variant of `style.css`.
+///
+/// [`include_assets!`]: crate::include_assets
+#[derive(Debug)]
+pub struct EmbeddedSource {
+    files: HashMap<&'static str, &'static EmbeddedFile>,
+}
+
+impl EmbeddedSource {
+    pub fn new(files: &'static [EmbeddedFile]) -> Self {
+        let files = files.iter().map(|file| (file.path, file)).collect();
+        EmbeddedSource { files }
+    }
+
+    /// Looks up a file, or a precompressed variant of one
+    fn get(&self, path: &str) -> io::Result<(Metadata, &'static [u8])> {
+        if let Some(file) = self.files.get(path) {
+            return Ok((file.metadata(), file.contents));
+        }
+
+        let (base, extension) = path.rsplit_once('.').ok_or(io::ErrorKind::NotFound)?;
+        let file = self.files.get(base).ok_or(io::ErrorKind::NotFound)?;
+        let (encoding, contents) = file
+            .encoded
+            .iter()
+            .find(|(encoding, _)| encoding.extension() == extension)
+            .ok_or(io::ErrorKind::NotFound)?;
+
+        let metadata = file.metadata();
+        let metadata = Metadata {
+            len: contents.len() as u64,
+            version: format!("{}-{}", metadata.version, encoding.name()),
+            ..metadata
+        };
+        Ok((metadata, contents))
+    }
+}
+
+#[rocket::async_trait]
+impl AssetSource for EmbeddedSource {
+    async fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        Ok(self.get(path)?.0)
+    }
+
+    async fn open(&self, path: &str) -> io::Result<Box<dyn AssetReader>> {
+        Ok(Box::new(Cursor::new(self.get(path)?.1)))
+    }
+
+    async fn paths(&self) -> io::Result<Vec<String>> {
+        Ok(self.files.keys().map(|path| path.to_string()).collect())
+    }
+
+    async fn digest(&self, path: &str) -> io::Result<[u8; 32]> {
+        let file = self.files.get(path).ok_or(io::ErrorKind::NotFound)?;
+        Ok(file.digest)
+    }
+
+    fn describe(&self) -> String {
+        format!("{} embedded files", self.files.len())
+    }
+}
+
+/// Embeds an assets directory, to be called from a build script
+///
+/// Every file is hashed (for fingerprinting and entity tags), and its media type guessed. For
+/// each encoding, a precompressed sidecar (e.g. `style.css.br`) is embedded if present, otherwise
+/// compressible files are compressed with brotli and gzip, with the `compress` feature (unless
+/// disabled with `Builder::compress()`).
+///
+/// Hidden files aren't embedded: dotfiles (except for `.well-known`) unless enabled with
+/// [`Builder::dotfiles()`], and paths matching [`Builder::ignore()`] patterns.
+#[derive(Debug, Clone)]
+pub struct Builder {
+    dir: PathBuf,
+    #[cfg(feature = "compress")]
+    compress: bool,
+    filter: Filter,
+}
+
+impl Builder {
+    /// Embeds the given directory, relative to the crate's manifest
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Builder {
+            dir: dir.into(),
+            #[cfg(feature = "compress")]
+            compress: true,
+            filter: Filter::default(),
+        }
+    }
+
+    /// Whether to compress assets without sidecars at build time (`true` by default)
+    #[cfg(feature = "compress")]
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Whether to embed dotfiles, besides `.well-known` (`false` by default)
+    pub fn dotfiles(mut self, dotfiles: bool) -> Self {
+        self.filter.dotfiles = dotfiles;
+        self
+    }
+
+    /// Leaves out the paths matching a `.gitignore`-style pattern (e.g. `*.swp` or `drafts/`)
+    pub fn ignore(mut self, pattern: &str) -> Self {
+        self.filter.ignore.push(Glob::new(pattern));
+        self
+    }
+
+    /// Generates the code included by [`include_assets!`] in `OUT_DIR`, asking cargo to rerun
+    /// the build script when the directory changes
+    ///
+    /// [`include_assets!`]: crate::include_assets
+    pub fn build(self) -> io::Result<()> {
+        let out_dir = env::var_os("OUT_DIR").map(PathBuf::from).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "OUT_DIR isn't set, assets must be embedded from a build script",
+            )
+        })?;
+        let dir = match env::var_os("CARGO_MANIFEST_DIR") {
+            Some(manifest_dir) => PathBuf::from(manifest_dir).join(&self.dir),
+            None => self.dir.clone(),
+        };
+        println!("cargo:rerun-if-changed={}", dir.display());
+
+        let code = self.generate(&fs::canonicalize(&dir)?, &out_dir)?;
+        fs::write(out_dir.join(GENERATED), code)
+    }
+
+    /// Generates the embedded files' slice, writing compressed variants to `out_dir`
+    fn generate(&self, root: &Path, out_dir: &Path) -> io::Result<String> {
+        let mut files = source::walk(root)?;
+        files.retain(|(relative, _)| self.filter.allows(relative));
+        files.sort();
+        let paths = files
+            .iter()
+            .map(|(relative, _)| relative.as_str())
+            .collect::<HashSet<_>>();
+
+        let variants_dir = out_dir.join("rocket_assets");
+        fs::create_dir_all(&variants_dir)?;
+
+        let mut code = String::from("&[\n");
+        for (index, (relative, target)) in files.iter().enumerate() {
+            let contents = fs::read(target)?;
+            let digest: [u8; 32] = Sha256::digest(&contents).into();
+            // Without a charset, which is configured when serving
+            let content_type = Path::new(relative).extension().and_then(|extension| {
+                mime::known(&extension.to_string_lossy().to_ascii_lowercase())
+            });
+            let modified = fs::metadata(target)?
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|mtime| mtime.as_secs());
+
+            let mut encoded = String::new();
+            for encoding in Encoding::DEFAULT {
+                let sidecar = format!("{}.{}", relative, encoding.extension());
+                let variant = if paths.contains(sidecar.as_str()) {
+                    root.join(&sidecar)
+                } else if let Some(compressed) =
+                    self.compressed(&contents, content_type.as_ref(), encoding)?
+                {
+                    let path = variants_dir.join(format!("{}.{}", index, encoding.extension()));
+                    fs::write(&path, compressed)?;
+                    path
+                } else {
+                    continue;
+                };
+                let _ = write!(
+                    encoded,
+                    "(::rocket_assets_fairing::Encoding::{:?}, include_bytes!({:?})), ",
+                    encoding,
+                    variant.to_string_lossy()
+                );
+            }
+
+            let _ = writeln!(
+                code,
+                "    ::rocket_assets_fairing::embed::EmbeddedFile {{\n        \
+                path: {:?},\n        \
+                contents: include_bytes!({:?}),\n        \
+                content_type: {:?},\n        \
+                digest: {:?},\n        \
+                modified: {:?},\n        \
+                encoded: &[{}],\n    \
+                }},",
+                relative,
+                target.to_string_lossy(),
+                content_type.map(|content_type| content_type.to_string()),
+                digest,
+                modified,
+                encoded,
+            );
+        }
+        code.push(']');
+        Ok(code)
+    }
+
+    /// Compresses a file without a sidecar, `None` if it isn't worth it
+    #[cfg(feature = "compress")]
+    fn compressed(
+        &self,
+        contents: &[u8],
+        content_type: Option<&ContentType>,
+        encoding: Encoding,
+    ) -> io::Result<Option<Vec<u8>>> {
+        if !self.compress
+            || !content_type.is_some_and(compression::is_compressible)
+            || contents.len() < MIN_COMPRESSED_SIZE
+            || !Compressor::supports(encoding)
+        {
+            return Ok(None);
+        }
+        let compressed = compression::encode(contents, encoding)?;
+        Ok(Some(compressed).filter(|compressed| compressed.len() < contents.len()))
+    }
+
+    /// Files are only compressed with the `compress` feature
+    #[cfg(not(feature = "compress"))]
+    fn compressed(
+        &self,
+        _contents: &[u8],
+        _content_type: Option<&ContentType>,
+        _encoding: Encoding,
+    ) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Assets;
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::local::asynchronous::Client;
+
+    /// The generated entry of a file
+    #[cfg(feature = "compress")]
+    fn entry<'a>(code: &'a str, path: &str) -> Option<&'a str> {
+        let path = format!("path: {:?},", path);
+        code.split("EmbeddedFile {")
+            .find(|entry| entry.contains(&path))
+    }
+
+    #[test]
+    #[cfg(feature = "compress")]
+    fn generates_files_and_variants() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(dir.path()).unwrap();
+        let style = "body { color: black; }\n".repeat(100);
+        fs::write(root.join("style.css"), &style).unwrap();
+        fs::write(root.join("style.css.br"), "precompressed").unwrap();
+        fs::write(root.join("app.js"), "console.log('hi');").unwrap();
+        fs::write(root.join("photo.png"), vec![0; 4096]).unwrap();
+        fs::write(root.join(".env"), "SECRET=1").unwrap();
+        fs::create_dir_all(root.join(".well-known")).unwrap();
+        fs::write(root.join(".well-known/security.txt"), "Contact: me").unwrap();
+        fs::create_dir_all(root.join("drafts")).unwrap();
+        fs::write(root.join("drafts/post.html"), "<p>WIP</p>").unwrap();
+        let out = tempfile::tempdir().unwrap();
+
+        let builder = Builder::new(&root).ignore("drafts/");
+        let code = builder.generate(&root, out.path()).unwrap();
+        let include = |path: PathBuf| format!("include_bytes!({:?})", path.to_string_lossy());
+
+        // Sidecars are used when present, other encodings are compressed into `out_dir`
+        let style_entry = entry(&code, "style.css").unwrap();
+        assert!(style_entry.contains(&include(root.join("style.css"))));
+        assert!(style_entry.contains("content_type: Some(\"text/css\")"));
+        let digest: [u8; 32] = Sha256::digest(&style).into();
+        assert!(style_entry.contains(&format!("digest: {:?},", digest)));
+        let brotli = format!("Encoding::Brotli, {})", include(root.join("style.css.br")));
+        assert!(style_entry.contains(&brotli));
+        let variants = fs::read_dir(out.path().join("rocket_assets")).unwrap();
+        let variants = variants.map(|e| e.unwrap().path()).collect::<Vec<_>>();
+        assert_eq!(variants.len(), 1);
+        let gzip = fs::read(&variants[0]).unwrap();
+        assert!(gzip.len() < style.len());
+        assert!(style_entry.contains(&format!(
+            "Encoding::Gzip, {})",
+            include(variants[0].clone())
+        )));
+        assert!(!style_entry.contains("Zstd"));
+
+        // Small and incompressible files aren't compressed
+        assert!(entry(&code, "app.js").unwrap().contains("encoded: &[],"));
+        assert!(entry(&code, "photo.png").unwrap().contains("encoded: &[],"));
+
+        assert!(entry(&code, ".well-known/security.txt").is_some());
+        assert!(entry(&code, ".env").is_none());
+        assert!(entry(&code, "drafts/post.html").is_none());
+
+        let builder = Builder::new(&root).dotfiles(true).compress(false);
+        let code = builder.generate(&root, out.path()).unwrap();
+        assert!(entry(&code, ".env").is_some());
+        assert!(entry(&code, "drafts/post.html").is_some());
+        assert!(!entry(&code, "style.css")
+            .unwrap()
+            .contains("Encoding::Gzip"));
+    }
+
+    static FILES: &[EmbeddedFile] = &[EmbeddedFile {
+        path: "style.css",
+        contents: b"body {}",
+        content_type: Some("text/css"),
+        digest: [7; 32],
+        modified: Some(1445412480),
+        encoded: &[(Encoding::Brotli, b"brotli")],
+    }];
+
+    #[rocket::async_test]
+    async fn variants_are_virtual_sidecars() {
+        let source = EmbeddedSource::new(FILES);
+        assert_eq!(source.paths().await.unwrap(), ["style.css"]);
+
+        let metadata = source.metadata("style.css").await.unwrap();
+        assert_eq!(metadata.len, 7);
+        assert_eq!(metadata.version, "07".repeat(16));
+        assert_eq!(metadata.content_type, Some(ContentType::CSS));
+
+        let sidecar = source.metadata("style.css.br").await.unwrap();
+        assert_eq!(sidecar.len, 6);
+        assert_eq!(sidecar.version, format!("{}-br", metadata.version));
+        assert_eq!(sidecar.modified, metadata.modified);
+        assert_eq!(
+            source::read(&source, "style.css.br").await.unwrap(),
+            b"brotli"
+        );
+
+        for missing in ["style.css.gz", "style.br", "missing.css.br", "style"] {
+            let error = source.metadata(missing).await.unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        }
+        let error = source.digest("style.css.br").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn configured_charset_applies() {
+        let config = Toml::string("[default]\nassets_charset = \"iso-8859-1\"");
+        let figment = rocket::Config::figment().merge(config.nested());
+        let rocket = rocket::custom(figment)
+            .attach(Assets::fairing_embedded(FILES))
+            .mount("/", Assets::routes());
+        let client = Client::tracked(rocket).await.expect("valid rocket");
+
+        let response = client.get("/style.css").dispatch().await;
+        let content_type = response.headers().get_one("Content-Type");
+        assert_eq!(content_type, Some("text/css; charset=iso-8859-1"));
+    }
+}
@@ -0,0 +1,157 @@
+//! Content encodings and `Accept-Encoding` negotiation.
+use rocket::serde::Deserialize;
+use rocket::Request;
+use std::fmt;
+
+/// A content encoding (compression format) assets can be served with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub enum Encoding {
+    /// Brotli (`br`), served from `.br` sidecar files
+    #[serde(rename = "br")]
+    Brotli,
+    /// Zstandard (`zstd`), served from `.zst` sidecar files
+    #[serde(rename = "zstd")]
+    Zstd,
+    /// Gzip (`gzip`), served from `.gz` sidecar files
+    #[serde(rename = "gzip")]
+    Gzip,
+}
+
+impl Encoding {
+    /// Encodings allowed when `assets_encodings` isn't configured, by priority
+    pub(crate) const DEFAULT: [Encoding; 3] = [Encoding::Brotli, Encoding::Zstd, Encoding::Gzip];
+
+    /// The content coding, as used in `Accept-Encoding` and `Content-Encoding`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Extension of the precompressed sidecar file (e.g. `app.js.br`)
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zst",
+            Encoding::Gzip => "gz",
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Picks the preferred encoding out of `available` (ordered by priority), according to the
+/// request's `Accept-Encoding` header
+///
+/// Encodings with a higher quality value win, ties are broken by the order in `available`.
+pub(crate) fn negotiate<I>(req: &Request<'_>, available: I) -> Option<Encoding>
+where
+    I: IntoIterator<Item = Encoding>,
+{
+    let accept = req
+        .headers()
+        .get("Accept-Encoding")
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in available {
+        let q = quality(&accept, encoding.name());
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Quality value given to `coding` by an `Accept-Encoding` header (`0` if not acceptable)
+fn quality(accept: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+    for element in accept.split(',') {
+        let mut params = element.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let q = params
+            .filter_map(|p| p.strip_prefix("q=").or_else(|| p.strip_prefix("Q=")))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if name.eq_ignore_ascii_case(coding) {
+            return q;
+        } else if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+    wildcard.unwrap_or(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+
+    fn negotiate(accept: Option<&'static str>, available: &[Encoding]) -> Option<Encoding> {
+        let client = Client::debug(rocket::build()).unwrap();
+        let mut request = client.get("/");
+        if let Some(accept) = accept {
+            request = request.header(Header::new("Accept-Encoding", accept));
+        }
+        super::negotiate(request.inner(), available.iter().copied())
+    }
+
+    #[test]
+    fn parses_quality_values() {
+        assert_eq!(quality("gzip", "gzip"), 1.0);
+        assert_eq!(quality("br;q=0.5, gzip;q=0.8", "br"), 0.5);
+        assert_eq!(quality("br ; Q=0.5", "br"), 0.5);
+        assert_eq!(quality("GZIP;q=0.3", "gzip"), 0.3);
+        assert_eq!(quality("br;q=invalid", "br"), 1.0);
+        assert_eq!(quality("gzip", "br"), 0.0);
+        assert_eq!(quality("", "br"), 0.0);
+        // Wildcards only apply to codings that aren't listed
+        assert_eq!(quality("*;q=0.2", "br"), 0.2);
+        assert_eq!(quality("br;q=0.7, *;q=0.2", "br"), 0.7);
+        assert_eq!(quality("*, br;q=0", "br"), 0.0);
+    }
+
+    #[test]
+    fn negotiates_by_quality() {
+        use Encoding::*;
+        assert_eq!(negotiate(None, &[Brotli, Gzip]), None);
+        assert_eq!(negotiate(Some("gzip"), &[Brotli, Gzip]), Some(Gzip));
+        assert_eq!(
+            negotiate(Some("br;q=0.5, gzip"), &[Brotli, Gzip]),
+            Some(Gzip)
+        );
+        assert_eq!(negotiate(Some("identity"), &[Brotli, Gzip]), None);
+        assert_eq!(negotiate(Some("br"), &[]), None);
+    }
+
+    #[test]
+    fn excludes_unacceptable_encodings() {
+        use Encoding::*;
+        assert_eq!(negotiate(Some("br;q=0"), &[Brotli, Gzip]), None);
+        assert_eq!(negotiate(Some("br;q=0, gzip"), &[Brotli, Gzip]), Some(Gzip));
+        assert_eq!(negotiate(Some("*, br;q=0"), &[Brotli, Gzip]), Some(Gzip));
+        assert_eq!(negotiate(Some("*;q=0"), &[Brotli, Gzip]), None);
+    }
+
+    #[test]
+    fn ties_follow_the_configured_order() {
+        use Encoding::*;
+        assert_eq!(negotiate(Some("gzip, br"), &[Brotli, Gzip]), Some(Brotli));
+        assert_eq!(negotiate(Some("gzip, br"), &[Gzip, Brotli]), Some(Gzip));
+        assert_eq!(negotiate(Some("*"), &[Zstd, Brotli, Gzip]), Some(Zstd));
+        assert_eq!(
+            negotiate(Some("gzip;q=0.5, *;q=0.5"), &[Brotli, Gzip]),
+            Some(Brotli)
+        );
+    }
+}
@@ -0,0 +1,143 @@
+//! The error opening an asset, which can also be responded with.
+use crate::resolve::PathError;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, Responder};
+use rocket::{error_, info_, warn_};
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// Reason why an asset couldn't be opened, along with the requested path
+///
+/// It can be returned from routes (e.g. as `Result<Asset, AssetError>`), responding with the
+/// matching status (`404 Not Found`, including for hidden assets, `403 Forbidden` or
+/// `500 Internal Server Error`) and logging the requested path:
+/// ```rust,no_run
+/// # #[macro_use] extern crate rocket;
+/// use rocket_assets_fairing::{Asset, AssetError, Assets};
+///
+/// #[get("/style.css")]
+/// async fn style(assets: &Assets) -> Result<Asset, AssetError> {
+///     assets.open("style.css").await
+/// }
+/// ```
+#[derive(Debug)]
+pub enum AssetError {
+    /// No asset exists at the path
+    NotFound { path: String },
+    /// The asset is a dotfile or matches an ignored pattern, and is treated as missing
+    Hidden { path: String },
+    /// The path was refused, e.g. because it escapes the assets directory
+    Forbidden { path: String, reason: PathError },
+    /// The asset's type isn't allowed (see `assets_types`)
+    Disallowed { path: String },
+    /// The asset's source failed reading it
+    Io { path: String, error: io::Error },
+}
+
+impl AssetError {
+    /// Classifies an error of an [`AssetSource`](crate::AssetSource) looking up `path`
+    pub(crate) fn new(path: String, error: io::Error) -> Self {
+        let reason = error
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<PathError>())
+            .copied();
+        match (error.kind(), reason) {
+            (_, Some(reason)) => AssetError::Forbidden { path, reason },
+            (io::ErrorKind::NotFound, None) => AssetError::NotFound { path },
+            (_, None) => AssetError::Io { path, error },
+        }
+    }
+
+    /// The path that was requested
+    pub fn path(&self) -> &str {
+        match self {
+            AssetError::NotFound { path }
+            | AssetError::Hidden { path }
+            | AssetError::Forbidden { path, .. }
+            | AssetError::Disallowed { path }
+            | AssetError::Io { path, .. } => path,
+        }
+    }
+
+    /// The status to respond with
+    pub fn status(&self) -> Status {
+        match self {
+            AssetError::NotFound { .. } | AssetError::Hidden { .. } => Status::NotFound,
+            AssetError::Forbidden { .. } | AssetError::Disallowed { .. } => Status::Forbidden,
+            AssetError::Io { .. } => Status::InternalServerError,
+        }
+    }
+
+    /// Logs the error, the more important the more it's likely to be a misconfiguration
+    pub(crate) fn log(&self) {
+        match self {
+            AssetError::NotFound { .. } | AssetError::Hidden { .. } => info_!("{}.", self),
+            AssetError::Forbidden { .. } | AssetError::Disallowed { .. } => warn_!("{}.", self),
+            AssetError::Io { .. } => error_!("{}.", self),
+        }
+    }
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssetError::NotFound { path } => write!(f, "Asset '{}' not found", path),
+            AssetError::Hidden { path } => write!(f, "Asset '{}' is hidden", path),
+            AssetError::Forbidden { path, reason } => {
+                write!(f, "Asset '{}' forbidden: {}", path, reason)
+            }
+            AssetError::Disallowed { path } => write!(f, "Asset '{}' has a disallowed type", path),
+            AssetError::Io { path, error } => {
+                write!(f, "Failed to open asset '{}': {}", path, error)
+            }
+        }
+    }
+}
+
+impl Error for AssetError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AssetError::NotFound { .. }
+            | AssetError::Hidden { .. }
+            | AssetError::Disallowed { .. } => None,
+            AssetError::Forbidden { reason, .. } => Some(reason),
+            AssetError::Io { error, .. } => Some(error),
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for AssetError {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        self.log();
+        Err(self.status())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_errors_are_classified() {
+        let e = AssetError::new("a.css".into(), io::ErrorKind::NotFound.into());
+        assert!(matches!(e, AssetError::NotFound { .. }));
+        assert_eq!(e.status(), Status::NotFound);
+
+        let e = AssetError::new("../a.css".into(), PathError::ParentSegment.into());
+        assert!(matches!(
+            e,
+            AssetError::Forbidden {
+                reason: PathError::ParentSegment,
+                ..
+            }
+        ));
+        assert_eq!(e.status(), Status::Forbidden);
+
+        let e = AssetError::new("a.css".into(), io::ErrorKind::PermissionDenied.into());
+        assert!(matches!(e, AssetError::Io { .. }));
+        assert_eq!(e.status(), Status::InternalServerError);
+        assert_eq!(e.path(), "a.css");
+    }
+}
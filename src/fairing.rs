@@ -0,0 +1,549 @@
+//! The fairing loading (and reporting) an asset collection.
+use crate::collection::{self, Collections};
+#[cfg(feature = "compress")]
+use crate::compression::Compressor;
+use crate::config::{self, AssetsConfig};
+use crate::decorate;
+use crate::filter::Filter;
+use crate::glob::Glob;
+use crate::handler;
+use crate::live_reload::{self, LiveReload};
+use crate::manifest::Manifest;
+use crate::mime::Mime;
+use crate::source::{AssetSource, FileSource};
+use crate::types::Disallowed;
+#[cfg(feature = "watch")]
+use crate::watch;
+use crate::{Assets, Encoding};
+#[cfg(feature = "watch")]
+use rocket::error_;
+use rocket::tokio::sync::broadcast;
+use rocket::{
+    error,
+    fairing::{self, Fairing, Info, Kind},
+    info, info_, warn, Build, Orbit, Request, Response, Rocket,
+};
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+pub(crate) struct AssetsFairing {
+    /// Name of the collection, `None` for the default one
+    name: Option<String>,
+    /// Source to use instead of the configured directories
+    source: Option<Arc<dyn AssetSource>>,
+    /// Configuration to use instead of Rocket's
+    config: Option<AssetsConfig>,
+}
+
+impl AssetsFairing {
+    pub fn new(name: Option<String>) -> Self {
+        AssetsFairing {
+            name,
+            source: None,
+            config: None,
+        }
+    }
+
+    pub fn with_source(source: Arc<dyn AssetSource>) -> Self {
+        AssetsFairing {
+            name: None,
+            source: Some(source),
+            config: None,
+        }
+    }
+
+    pub fn with_config(name: Option<String>, config: AssetsConfig) -> Self {
+        AssetsFairing {
+            name,
+            source: None,
+            config: Some(config),
+        }
+    }
+
+    /// Configuration key for this collection: `assets_<key>` for the default collection, and
+    /// `assets.<name>.<key>` for named ones
+    fn key(&self, key: &str) -> String {
+        match &self.name {
+            None => format!("assets_{}", key),
+            Some(name) => format!("assets.{}.{}", name, key),
+        }
+    }
+
+    /// The collection's configuration, printing errors
+    fn config(&self, rocket: &Rocket<Build>) -> Result<AssetsConfig, ()> {
+        if let Some(config) = &self.config {
+            return Ok(config.clone());
+        }
+        AssetsConfig::extract(rocket.figment(), self.name.as_deref())
+    }
+
+    /// Opens the configured directories (`assets/` or `assets/<name>/` by default)
+    fn load_layers(&self, dirs: Option<Vec<PathBuf>>) -> Result<Vec<Arc<dyn AssetSource>>, ()> {
+        let dirs = dirs.unwrap_or_else(|| {
+            vec![match &self.name {
+                None => "assets/".into(),
+                Some(name) => PathBuf::from("assets").join(name),
+            }]
+        });
+        let mut sources = Vec::new();
+        for dir in dirs {
+            match FileSource::new(&dir) {
+                Ok(source) => sources.push(Arc::new(source) as Arc<dyn AssetSource>),
+                Err(e) => {
+                    error!("Invalid assets directory '{}': {}.", dir.display(), e);
+                    return Err(());
+                }
+            }
+        }
+        Ok(sources)
+    }
+
+    /// Loads the collection from its configuration
+    async fn load(&self, rocket: &Rocket<Build>) -> Result<Assets, ()> {
+        let config = self.config(rocket)?;
+
+        #[cfg(feature = "compress")]
+        let compressor = match config.compression.enabled {
+            true => Some(Arc::new(Compressor::new(config.compression))),
+            false => None,
+        };
+        #[cfg(not(feature = "compress"))]
+        if config.compression.enabled {
+            error!("Compressing assets on the fly needs the `compress` feature.");
+            return Err(());
+        }
+
+        let layers = match &self.source {
+            Some(source) => vec![source.clone()],
+            None => self.load_layers(config.dir)?,
+        };
+        let filter = Filter {
+            dotfiles: config.dotfiles,
+            ignore: config.ignore,
+        };
+        let manifest = match Manifest::build(&layers, &filter, &config.integrity).await {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                error!("Failed to fingerprint assets: {}.", e);
+                return Err(());
+            }
+        };
+
+        let debug = rocket.figment().profile() == rocket::Config::DEBUG_PROFILE;
+        let mut live_reload = config.live_reload;
+        if live_reload && !debug {
+            warn!(
+                "Ignoring `{}` outside of the debug profile.",
+                self.key("live_reload")
+            );
+            live_reload = false;
+        }
+        #[cfg(not(feature = "watch"))]
+        if live_reload || config.reload == Some(true) {
+            let key = self.key(if live_reload { "live_reload" } else { "reload" });
+            error!("`{}` needs the `watch` feature.", key);
+            return Err(());
+        }
+        // Live reloading relies on the watcher
+        let reload = cfg!(feature = "watch") && (live_reload || config.reload.unwrap_or(debug));
+
+        let base = match config.base_url {
+            Some(url) => match config::base_url(&url) {
+                Ok(base) => base,
+                Err(e) => {
+                    error!("Invalid `{}` '{}': {}.", self.key("base_url"), url, e);
+                    return Err(());
+                }
+            },
+            None => mounted_base(rocket, self.name.as_deref()),
+        };
+        let mut decorate = Vec::new();
+        for prefix in config.decorate {
+            match decorate::prefix(&prefix) {
+                Ok(prefix) => decorate.push(prefix),
+                Err(e) => {
+                    error!("Invalid `{}` '{}': {}.", self.key("decorate"), prefix, e);
+                    return Err(());
+                }
+            }
+        }
+
+        Ok(Assets {
+            name: self.name.clone(),
+            layers,
+            cache_max_age: config.max_age,
+            cache_rules: config.cache,
+            encodings: config.encodings,
+            #[cfg(feature = "compress")]
+            compressor,
+            filter,
+            types: config.types,
+            mime: Mime {
+                types: config.mime_types,
+                charset: Some(config.charset).filter(|charset| !charset.is_empty()),
+                sniff: config.sniff,
+            },
+            integrity: config.integrity,
+            base,
+            decorate: decorate::Decorate::new(decorate, config.decorate_max_size),
+            manifest: Arc::new(RwLock::new(manifest)),
+            reload,
+            live_reload,
+            changes: broadcast::channel(live_reload::CHANGES_CAPACITY).0,
+        })
+    }
+}
+
+/// Path the collection's handler is mounted at (without its trailing `/`), the root if it isn't
+fn mounted_base(rocket: &Rocket<Build>, name: Option<&str>) -> String {
+    let route_name = handler::route_name(name);
+    let route = rocket
+        .routes()
+        .find(|route| route.name.as_deref() == Some(route_name.as_str()));
+    let base = route.map_or("/", |route| route.uri.base());
+    base.trim_end_matches('/').to_string()
+}
+
+/// Mounts the live reload routes of a collection, if enabled
+fn mount_live_reload(rocket: Rocket<Build>, assets: &Assets) -> Rocket<Build> {
+    match assets.live_reload {
+        true => {
+            let base = live_reload::base(assets.name());
+            rocket.mount(base, LiveReload::routes(assets.name.clone()))
+        }
+        false => rocket,
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for AssetsFairing {
+    fn info(&self) -> Info {
+        let kind = Kind::Response | Kind::Ignite | Kind::Liftoff;
+        Info {
+            kind,
+            name: "Static Assets",
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let name = match &self.name {
+            None => {
+                return match self.load(&rocket).await {
+                    Ok(assets) => Ok(mount_live_reload(rocket, &assets).manage(assets)),
+                    Err(()) => Err(rocket),
+                };
+            }
+            Some(name) => name,
+        };
+
+        // The first named collection registers every configured one
+        let rocket = match rocket.state::<Collections>() {
+            Some(_) => rocket,
+            None => match Collections::configured(rocket.figment()) {
+                Ok(collections) => rocket.manage(collections),
+                Err(()) => return Err(rocket),
+            },
+        };
+        let collections = rocket
+            .state::<Collections>()
+            .expect("Collections registered above");
+        if let Err(e) = collections.check(name, self.config.is_some()) {
+            error!("{}", e);
+            return Err(rocket);
+        }
+
+        let assets = match self.load(&rocket).await {
+            Ok(assets) => assets,
+            Err(()) => return Err(rocket),
+        };
+        let rocket = mount_live_reload(rocket, &assets);
+        let collections = rocket
+            .state::<Collections>()
+            .expect("Collections registered above");
+        if let Err(e) = collections.register(name, assets, self.config.is_some()) {
+            error!("{}", e);
+            return Err(rocket);
+        }
+        Ok(rocket)
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        use rocket::{figment::Source, log::PaintExt, yansi::Paint};
+
+        let state =
+            collection::find(rocket, self.name.as_deref()).expect("Assets registered in on_ignite");
+
+        match &self.name {
+            None => info!("{}{}:", "📐 ".emoji(), "Assets".magenta()),
+            Some(name) => info!("{}{} ({}):", "📐 ".emoji(), "Assets".magenta(), name),
+        }
+        let manifest = state.manifest();
+        match &state.layers[..] {
+            [source] => match source.root() {
+                Some(dir) => info_!("directory: {}", Source::from(dir).white()),
+                None => info_!("source: {}", source.describe().white()),
+            },
+            layers => {
+                for (i, source) in layers.iter().enumerate() {
+                    let served = manifest
+                        .entries()
+                        .filter(|(_, entry)| entry.layers[0] == i)
+                        .count();
+                    let source = match source.root() {
+                        Some(dir) => Source::from(dir).to_string(),
+                        None => source.describe(),
+                    };
+                    info_!("layer {}: {} ({} files)", i, source.white(), served);
+                }
+            }
+        }
+        match state.base.as_str() {
+            "" => info_!("base url: {}", "/".white()),
+            base => info_!("base url: {}", base.white()),
+        }
+        info_!("cache max age: {}", state.cache_max_age.white());
+        for rule in &state.cache_rules {
+            let mut matches = rule.pattern.iter().map(Glob::to_string).collect::<Vec<_>>();
+            matches.extend(rule.extensions.iter().map(|e| format!("*.{}", e)));
+            let matches = match matches.is_empty() {
+                true => "*".to_string(),
+                false => matches.join(" & "),
+            };
+            let policy = rule.policy.header().unwrap_or_default();
+            info_!("cache rule: {} => {}", matches, policy.white());
+        }
+        let encodings = state
+            .encodings
+            .iter()
+            .map(Encoding::name)
+            .collect::<Vec<_>>();
+        info_!("precompressed encodings: {}", encodings.join(", ").white());
+        #[cfg(feature = "compress")]
+        info_!(
+            "on the fly compression: {}",
+            state.compressor.is_some().white()
+        );
+        info_!("dotfiles: {}", state.filter.dotfiles.white());
+        if !state.filter.ignore.is_empty() {
+            let ignore = state.filter.ignore.iter().map(Glob::to_string);
+            info_!("ignored: {}", ignore.collect::<Vec<_>>().join(", ").white());
+        }
+        if !state.decorate.prefixes.is_empty() {
+            let prefixes = state.decorate.prefixes.join(", ");
+            info_!("decorated paths: {}", prefixes.white());
+        }
+        if let Some(allow) = &state.types.allow {
+            let allow = allow.iter().map(|pattern| pattern.to_string());
+            let others = match state.types.disallowed {
+                Disallowed::Refuse => "refused",
+                Disallowed::Download => "downloaded",
+            };
+            info_!(
+                "allowed types: {} (others {})",
+                allow.collect::<Vec<_>>().join(", ").white(),
+                others
+            );
+        }
+        info_!("sandboxed documents: {}", state.types.sandbox.white());
+        match &state.mime.charset {
+            Some(charset) => info_!("text charset: {}", charset.white()),
+            None => info_!("text charset: {}", "none".white()),
+        }
+        info_!("fingerprinted files: {}", manifest.len().white());
+        if !state.integrity.is_empty() {
+            let algorithms = state.integrity.iter().map(|a| a.name()).collect::<Vec<_>>();
+            info_!("integrity: {}", algorithms.join(" ").white());
+        }
+        info_!("hot reload: {}", state.reload.white());
+        if let Some(script) = state.live_reload_script() {
+            info_!("live reload script: {}", script.white());
+        }
+
+        let mut overrides = manifest
+            .entries()
+            .filter(|(_, entry)| entry.layers.len() > 1)
+            .collect::<Vec<_>>();
+        overrides.sort_by_key(|(path, _)| *path);
+        for (path, entry) in overrides {
+            let shadowed = entry.layers[1..]
+                .iter()
+                .map(|layer| layer.to_string())
+                .collect::<Vec<_>>();
+            info_!(
+                "{}: served from layer {} (overriding {})",
+                path,
+                entry.layers[0].white(),
+                shadowed.join(", ")
+            );
+        }
+        drop(manifest);
+
+        #[cfg(feature = "watch")]
+        if state.reload {
+            if let Err(e) = watch::spawn(state) {
+                error_!("Failed to watch assets for changes: {}.", e);
+            }
+        }
+    }
+
+    async fn on_response<'r>(&self, req: &'r Request<'_>, res: &mut Response<'r>) {
+        if let Some(assets) = collection::find(req.rocket(), self.name.as_deref()) {
+            decorate::decorate(assets, req, res).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Assets, AssetsConfig, CacheRule, MemorySource};
+    #[cfg(feature = "compress")]
+    use rocket::data::ByteUnit;
+    use rocket::error::ErrorKind;
+    use rocket::http::{Header, Status};
+    use rocket::local::blocking::Client;
+    use rocket::{Build, Rocket};
+    use std::time::Duration;
+
+    fn source() -> MemorySource {
+        let mut source = MemorySource::new();
+        source.insert("css/style.css", "body {}");
+        source
+    }
+
+    /// The URL of an asset, without its fingerprint
+    fn unhashed_url(rocket: Rocket<Build>, path: &str) -> String {
+        let client = Client::tracked(rocket).unwrap();
+        let assets = client.rocket().state::<Assets>().unwrap();
+        let url = assets.url(path).unwrap();
+        assert!(url.ends_with(".css"), "{}", url);
+        url.rsplit_once('.')
+            .unwrap()
+            .0
+            .rsplit_once('.')
+            .unwrap()
+            .0
+            .to_string()
+    }
+
+    #[test]
+    fn urls_are_under_the_mount_point() {
+        let rocket = rocket::build()
+            .attach(Assets::fairing_from_source(source()))
+            .mount("/static", Assets::routes());
+        let client = Client::tracked(rocket).unwrap();
+        let assets = client.rocket().state::<Assets>().unwrap();
+        let url = assets.url("css/style.css").unwrap();
+        assert!(url.starts_with("/static/css/style."), "{}", url);
+        assert_eq!(client.get(url).dispatch().status(), Status::Ok);
+
+        let rocket = rocket::build()
+            .attach(Assets::fairing_from_source(source()))
+            .mount("/", Assets::routes());
+        assert_eq!(unhashed_url(rocket, "css/style.css"), "/css/style");
+
+        // Routes serving assets by hand can't be detected
+        let rocket = rocket::build().attach(Assets::fairing_from_source(source()));
+        assert_eq!(unhashed_url(rocket, "css/style.css"), "/css/style");
+    }
+
+    #[test]
+    fn urls_are_under_the_base_url() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("css")).unwrap();
+        std::fs::write(dir.path().join("css/style.css"), "body {}").unwrap();
+        let config = AssetsConfig::new()
+            .dir(dir.path())
+            .base_url("https://cdn.example.com/static/");
+        let rocket = rocket::build()
+            .attach(Assets::fairing_with(config))
+            .mount("/assets", Assets::routes());
+        let cdn = "https://cdn.example.com/static/css/style";
+        assert_eq!(unhashed_url(rocket, "css/style.css"), cdn);
+
+        let figment = rocket::Config::figment().merge(("assets_base_url", "/static"));
+        let rocket = rocket::custom(figment)
+            .attach(Assets::fairing_from_source(source()))
+            .mount("/assets", Assets::routes());
+        assert_eq!(unhashed_url(rocket, "css/style.css"), "/static/css/style");
+
+        let figment = rocket::Config::figment().merge(("assets_base_url", "cdn.example.com"));
+        let rocket = rocket::custom(figment).attach(Assets::fairing_from_source(source()));
+        let error = Client::tracked(rocket).err().unwrap();
+        assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)));
+    }
+
+    #[test]
+    fn named_collections_have_their_own_mount_point() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("jquery.js"), "jQuery").unwrap();
+        let figment = rocket::Config::figment().merge(("assets.vendor.dir", dir.path()));
+        let rocket = rocket::custom(figment)
+            .attach(Assets::fairing_named("vendor"))
+            .mount("/vendor", Assets::routes_named("vendor"))
+            .mount("/assets", Assets::routes());
+        let client = Client::tracked(rocket).unwrap();
+        let vendor = crate::collection::find(client.rocket(), Some("vendor")).unwrap();
+        let url = vendor.url("jquery.js").unwrap();
+        assert!(url.starts_with("/vendor/jquery."), "{}", url);
+        assert_eq!(client.get(url).dispatch().status(), Status::Ok);
+    }
+
+    #[test]
+    fn named_collections_can_be_configured_in_code() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "<p>Hello</p>").unwrap();
+        std::fs::write(dir.path().join("style.css"), "body {}").unwrap();
+        let docs = AssetsConfig::new()
+            .dir(dir.path())
+            .cache_rule(CacheRule::new().matching("*.html").no_cache())
+            .cache_rule(
+                CacheRule::new()
+                    .extension("css")
+                    .public()
+                    .max_age(Duration::from_secs(60)),
+            );
+        let vendor = AssetsConfig::new().dir(dir.path());
+        #[cfg(feature = "compress")]
+        let vendor = vendor
+            .compression(true)
+            .compression_min_size(ByteUnit::Byte(0));
+        let rocket = rocket::build()
+            .attach(Assets::fairing_named_with("docs", docs))
+            .attach(Assets::fairing_named_with("vendor", vendor))
+            .mount("/docs", Assets::routes_named("docs"))
+            .mount("/vendor", Assets::routes_named("vendor"));
+        let client = Client::tracked(rocket).unwrap();
+
+        let response = client.get("/docs/index.html").dispatch();
+        assert_eq!(
+            response.headers().get_one("Cache-Control"),
+            Some("no-cache")
+        );
+        let response = client.get("/docs/style.css").dispatch();
+        let cache_control = response.headers().get_one("Cache-Control");
+        assert_eq!(cache_control, Some("public, max-age=60"));
+        let response = client
+            .get("/docs/style.css")
+            .header(Header::new("Accept-Encoding", "gzip"))
+            .dispatch();
+        assert_eq!(response.headers().get_one("Content-Encoding"), None);
+
+        let response = client
+            .get("/vendor/style.css")
+            .header(Header::new("Accept-Encoding", "gzip"))
+            .dispatch();
+        let encoding = cfg!(feature = "compress").then_some("gzip");
+        assert_eq!(response.headers().get_one("Content-Encoding"), encoding);
+    }
+
+    #[test]
+    fn named_collections_are_attached_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AssetsConfig::new().dir(dir.path());
+        let rocket = rocket::build()
+            .attach(Assets::fairing_named_with("vendor", config.clone()))
+            .attach(Assets::fairing_named_with("vendor", config));
+        let error = Client::tracked(rocket).err().unwrap();
+        assert!(matches!(error.kind(), ErrorKind::FailedFairings(_)));
+    }
+}
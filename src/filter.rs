@@ -0,0 +1,76 @@
+//! Hiding dotfiles and ignored paths from every lookup and listing.
+use crate::glob::Glob;
+
+/// The only dotfile directory served by default, holding well-known URIs (RFC 8615)
+const WELL_KNOWN: &str = ".well-known";
+
+/// Decides which assets are hidden, as if they didn't exist
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Filter {
+    /// Whether dotfiles (and files in dot directories) are served
+    pub dotfiles: bool,
+    /// Patterns of hidden paths
+    pub ignore: Vec<Glob>,
+}
+
+impl Filter {
+    /// Whether a relative path (with `/` separators) may be served
+    pub fn allows(&self, path: &str) -> bool {
+        let dotfile = path
+            .split('/')
+            .enumerate()
+            .any(|(i, segment)| segment.starts_with('.') && !(i == 0 && segment == WELL_KNOWN));
+        (self.dotfiles || !dotfile) && !self.ignore.iter().any(|glob| glob.matches(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dotfiles_are_hidden_but_well_known() {
+        let filter = Filter::default();
+        for path in [
+            ".env",
+            ".git/config",
+            "nested/.htaccess",
+            "nested/.well-known/x",
+            ".well-known/.secret",
+        ] {
+            assert!(!filter.allows(path), "{}", path);
+        }
+        for path in ["style.css", ".well-known/security.txt"] {
+            assert!(filter.allows(path), "{}", path);
+        }
+
+        let filter = Filter {
+            dotfiles: true,
+            ..Filter::default()
+        };
+        assert!(filter.allows(".git/config"));
+    }
+
+    #[test]
+    fn ignored_patterns_are_hidden() {
+        let filter = Filter {
+            dotfiles: false,
+            ignore: vec![
+                Glob::new("*~"),
+                Glob::new("drafts/"),
+                Glob::new("/secret.txt"),
+            ],
+        };
+        for path in [
+            "style.css~",
+            "nested/app.js~",
+            "drafts/post.html",
+            "secret.txt",
+        ] {
+            assert!(!filter.allows(path), "{}", path);
+        }
+        for path in ["style.css", "nested/secret.txt", "drafts.html"] {
+            assert!(filter.allows(path), "{}", path);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Glob patterns matched against relative asset paths.
+use rocket::serde::{Deserialize, Deserializer};
+use std::fmt;
+
+/// A glob pattern, following `.gitignore` conventions
+///
+/// `*` matches any sequence of characters but `/`, `?` any single one and `**` any amount of
+/// directories. Patterns without a `/` (other than a trailing one) match at any depth, while
+/// others are relative to the root. Matching a directory also matches everything inside of it,
+/// and a trailing `/` only matches directories.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Glob {
+    pattern: String,
+    segments: Vec<String>,
+    directory: bool,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Self {
+        let directory = pattern.ends_with('/');
+        let trimmed = pattern.trim_end_matches('/');
+        let anchored = trimmed.contains('/');
+
+        let mut segments = Vec::new();
+        if !anchored {
+            segments.push("**".to_string());
+        }
+        segments.extend(
+            trimmed
+                .trim_start_matches('/')
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .map(String::from),
+        );
+
+        Glob {
+            pattern: pattern.to_string(),
+            segments,
+            directory,
+        }
+    }
+
+    /// Whether a relative path (with `/` separators) matches, either itself or through one of
+    /// its parent directories
+    pub fn matches(&self, path: &str) -> bool {
+        let path = path.split('/').collect::<Vec<_>>();
+        let patterns = self.segments.iter().map(String::as_str).collect::<Vec<_>>();
+        let last = match self.directory {
+            true => path.len() - 1,
+            false => path.len(),
+        };
+        (1..=last).any(|len| matches_segments(&patterns, &path[..len]))
+    }
+}
+
+fn matches_segments(patterns: &[&str], path: &[&str]) -> bool {
+    match patterns.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| matches_segments(rest, &path[skip..])),
+        Some((pattern, rest)) => match path.split_first() {
+            Some((segment, path)) => {
+                matches_segment(pattern.as_bytes(), segment.as_bytes())
+                    && matches_segments(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+fn matches_segment(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skip| matches_segment(rest, &name[skip..])),
+        Some((b'?', rest)) => !name.is_empty() && matches_segment(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && matches_segment(rest, &name[1..]),
+    }
+}
+
+impl fmt::Debug for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.pattern, f)
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+impl<'de> Deserialize<'de> for Glob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|pattern| Glob::new(&pattern))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unanchored_patterns_match_at_any_depth() {
+        let glob = Glob::new("*.css");
+        assert!(glob.matches("style.css"));
+        assert!(glob.matches("css/vendor/style.css"));
+        assert!(!glob.matches("style.css.map"));
+        assert!(!glob.matches("style.scss"));
+
+        let glob = Glob::new("?.js");
+        assert!(glob.matches("a.js"));
+        assert!(glob.matches("js/b.js"));
+        assert!(!glob.matches("ab.js"));
+    }
+
+    #[test]
+    fn patterns_with_slashes_are_anchored() {
+        let glob = Glob::new("css/*.css");
+        assert!(glob.matches("css/style.css"));
+        assert!(!glob.matches("vendor/css/style.css"));
+        // `*` doesn't cross directories
+        assert!(!glob.matches("css/vendor/style.css"));
+
+        let glob = Glob::new("/style.css");
+        assert!(glob.matches("style.css"));
+        assert!(!glob.matches("css/style.css"));
+    }
+
+    #[test]
+    fn double_stars_match_any_directories() {
+        let glob = Glob::new("fonts/**/*.woff2");
+        assert!(glob.matches("fonts/a.woff2"));
+        assert!(glob.matches("fonts/inter/bold/a.woff2"));
+        assert!(!glob.matches("vendor/fonts/a.woff2"));
+
+        let glob = Glob::new("**/maps");
+        assert!(glob.matches("maps"));
+        assert!(glob.matches("js/maps/app.js.map"));
+
+        let glob = Glob::new("fonts/**");
+        assert!(glob.matches("fonts/inter/a.woff2"));
+        assert!(!glob.matches("fontsx/a.woff2"));
+    }
+
+    #[test]
+    fn matching_directories_matches_their_contents() {
+        let glob = Glob::new("drafts");
+        assert!(glob.matches("drafts"));
+        assert!(glob.matches("drafts/post.html"));
+        assert!(glob.matches("blog/drafts/post.html"));
+
+        // A trailing slash only matches directories
+        let glob = Glob::new("drafts/");
+        assert!(!glob.matches("drafts"));
+        assert!(glob.matches("drafts/post.html"));
+        assert!(glob.matches("blog/drafts/post.html"));
+        assert!(!glob.matches("blog/drafts"));
+
+        let glob = Glob::new("blog/drafts/");
+        assert!(glob.matches("blog/drafts/post.html"));
+        assert!(!glob.matches("old/blog/drafts/post.html"));
+    }
+}
@@ -0,0 +1,279 @@
+//! A ready-made handler serving every asset under its mount point.
+use crate::collection;
+use crate::error::AssetError;
+use rocket::http::{Method, Status};
+use rocket::route::{Handler, Outcome, Route};
+use rocket::{error_, Data, Request};
+use std::path::{Path, PathBuf};
+
+/// Handler serving any asset under the path it's mounted at, see [`Assets::routes()`]
+/// and [`Assets::routes_named()`]
+///
+/// Assets are opened with [`Assets::open()`], so they're served with the same (traversal safe)
+/// resolution and cache headers. Assets that can't be opened forward the request (with the
+/// [`AssetError`]'s status), letting other routes match, unless [`AssetsHandler::forward()`] is
+/// disabled.
+///
+/// With [`AssetsHandler::spa()`], it serves single-page applications instead: unknown paths
+/// without an extension (e.g. `/settings/profile`) get the application's entry point, while
+/// real assets are served as usual and missing ones (e.g. `/app.js`) still result in a `404`:
+/// ```rust,no_run
+/// # #[macro_use] extern crate rocket;
+/// use rocket_assets_fairing::Assets;
+///
+/// #[launch]
+/// fn rocket() -> _ {
+///     rocket::build()
+///         .attach(Assets::fairing())
+///         .mount("/", Assets::routes().spa("index.html").exclude("/api"))
+/// }
+/// ```
+///
+/// [`Assets::routes()`]: crate::Assets::routes
+/// [`Assets::routes_named()`]: crate::Assets::routes_named
+/// [`Assets::open()`]: crate::Assets::open
+/// [`AssetError`]: crate::AssetError
+#[derive(Debug, Clone)]
+pub struct AssetsHandler {
+    rank: isize,
+    forward: bool,
+    collection: Option<String>,
+    /// Entry point served for unknown paths, if serving a single-page application
+    fallback: Option<String>,
+    /// Path prefixes (relative to the mount point) never falling back
+    exclude: Vec<String>,
+}
+
+impl AssetsHandler {
+    /// The default rank used by the generated route
+    const DEFAULT_RANK: isize = 10;
+
+    pub(crate) fn new(collection: Option<String>) -> Self {
+        AssetsHandler {
+            rank: Self::DEFAULT_RANK,
+            forward: true,
+            collection,
+            fallback: None,
+            exclude: Vec::new(),
+        }
+    }
+
+    /// Sets the rank of the generated route (`10` by default)
+    pub fn rank(mut self, rank: isize) -> Self {
+        self.rank = rank;
+        self
+    }
+
+    /// Sets whether errors forward the request to other routes (the default), or fail it right
+    /// away, responding with the error's status (and thus the matching catcher)
+    pub fn forward(mut self, forward: bool) -> Self {
+        self.forward = forward;
+        self
+    }
+
+    /// Serves `fallback` (e.g. `index.html`) with a `no-cache` policy for unknown paths without
+    /// an extension, as single-page applications route them on the client
+    pub fn spa(mut self, fallback: &str) -> Self {
+        self.fallback = Some(fallback.to_string());
+        self
+    }
+
+    /// Never falls back for paths under a prefix (relative to the mount point, e.g. `/api`), so
+    /// that they result in a `404` instead
+    pub fn exclude(mut self, prefix: &str) -> Self {
+        self.exclude.push(prefix.trim_matches('/').to_string());
+        self
+    }
+
+    /// The file to serve instead of a failing request's path, if any
+    fn fallback(&self, segments: &[&str], error: &AssetError) -> Option<&str> {
+        let fallback = self.fallback.as_deref()?;
+        let missing = matches!(
+            error,
+            AssetError::NotFound { .. } | AssetError::Hidden { .. }
+        );
+        let extension = segments
+            .last()
+            .is_some_and(|name| Path::new(name).extension().is_some());
+        let excluded = self.exclude.iter().any(|prefix| {
+            let prefix = prefix
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .collect::<Vec<_>>();
+            segments.starts_with(&prefix)
+        });
+        match missing && !extension && !excluded {
+            true => Some(fallback),
+            false => None,
+        }
+    }
+}
+
+/// Name of the route serving a collection, used to find where it's mounted
+pub(crate) fn route_name(collection: Option<&str>) -> String {
+    match collection {
+        None => "Assets".to_string(),
+        Some(name) => format!("Assets ({})", name),
+    }
+}
+
+impl From<AssetsHandler> for Vec<Route> {
+    fn from(handler: AssetsHandler) -> Self {
+        let name = route_name(handler.collection.as_deref());
+        let mut route = Route::ranked(handler.rank, Method::Get, "/<path..>", handler);
+        route.name = Some(name.into());
+        vec![route]
+    }
+}
+
+#[rocket::async_trait]
+impl Handler for AssetsHandler {
+    async fn handle<'r>(&self, req: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let assets = match collection::find(req.rocket(), self.collection.as_deref()) {
+            Some(assets) => assets,
+            None => {
+                error_!("Assets handler mounted without attaching its `Assets` fairing.");
+                return Outcome::forward(data, Status::InternalServerError);
+            }
+        };
+
+        let segments = req.routed_segments(0..).collect::<Vec<_>>();
+        let error = match assets.open(segments.iter().collect::<PathBuf>()).await {
+            Ok(asset) => return Outcome::from(req, asset),
+            Err(e) => e,
+        };
+        let error = match self.fallback(&segments, &error) {
+            Some(fallback) => match assets.open(fallback).await {
+                Ok(asset) => return Outcome::from(req, asset.no_cache()),
+                Err(e) => e,
+            },
+            None => error,
+        };
+        match error {
+            e if self.forward => {
+                e.log();
+                Outcome::forward(data, e.status())
+            }
+            e => Outcome::from(req, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Assets, MemorySource};
+    use rocket::local::blocking::Client;
+
+    fn client() -> Client {
+        let mut source = MemorySource::new();
+        source.insert("index.html", "<main></main>");
+        source.insert("app.js", "main()");
+        let rocket = rocket::build()
+            .attach(Assets::fairing_from_source(source))
+            .mount("/", Assets::routes().spa("index.html").exclude("/api/"));
+        Client::tracked(rocket).unwrap()
+    }
+
+    #[test]
+    fn unknown_paths_fall_back() {
+        let client = client();
+        for path in ["/", "/settings/profile", "/.env"] {
+            let response = client.get(path).dispatch();
+            assert_eq!(response.status(), Status::Ok, "{}", path);
+            assert_eq!(
+                response.headers().get_one("Cache-Control"),
+                Some("no-cache")
+            );
+            assert_eq!(response.into_string().as_deref(), Some("<main></main>"));
+        }
+
+        let response = client.get("/app.js").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.headers().get_one("Cache-Control"),
+            Some("max-age=86400")
+        );
+    }
+
+    #[test]
+    fn asset_requests_and_excluded_paths_dont() {
+        let client = client();
+        for path in ["/missing.js", "/js/missing.js", "/api", "/api/users"] {
+            let response = client.get(path).dispatch();
+            assert_eq!(response.status(), Status::NotFound, "{}", path);
+        }
+        assert_eq!(client.get("/apis").dispatch().status(), Status::Ok);
+    }
+
+    #[rocket::get("/<_..>", rank = 20)]
+    fn lower() -> &'static str {
+        "lower"
+    }
+
+    #[rocket::get("/<_..>", rank = 5)]
+    fn higher() -> &'static str {
+        "higher"
+    }
+
+    fn source() -> MemorySource {
+        let mut source = MemorySource::new();
+        source.insert("app.js", "main()");
+        source
+    }
+
+    fn client_with(handler: AssetsHandler, routes: Vec<Route>) -> Client {
+        let rocket = rocket::build()
+            .attach(Assets::fairing_from_source(source()))
+            .mount("/", handler)
+            .mount("/", routes);
+        Client::tracked(rocket).unwrap()
+    }
+
+    #[test]
+    fn misses_forward_to_lower_ranked_routes() {
+        let client = client_with(Assets::routes(), rocket::routes![lower]);
+        let response = client.get("/app.js").dispatch();
+        assert_eq!(response.into_string().as_deref(), Some("main()"));
+        let response = client.get("/missing.js").dispatch();
+        assert_eq!(response.into_string().as_deref(), Some("lower"));
+
+        let client = client_with(Assets::routes().forward(false), rocket::routes![lower]);
+        let response = client.get("/missing.js").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        assert_ne!(response.into_string().as_deref(), Some("lower"));
+    }
+
+    #[test]
+    fn rank_is_honored() {
+        let client = client_with(Assets::routes(), rocket::routes![higher]);
+        let response = client.get("/app.js").dispatch();
+        assert_eq!(response.into_string().as_deref(), Some("higher"));
+
+        let client = client_with(Assets::routes().rank(1), rocket::routes![higher]);
+        let response = client.get("/app.js").dispatch();
+        assert_eq!(response.into_string().as_deref(), Some("main()"));
+        let route = client
+            .rocket()
+            .routes()
+            .find(|r| r.name.as_deref() == Some("Assets"));
+        assert_eq!(route.unwrap().rank, 1);
+    }
+
+    #[test]
+    fn traversal_is_forbidden() {
+        let client = client_with(Assets::routes(), Vec::new());
+        let paths = [
+            "/..",
+            "/../app.js",
+            "/js/../app.js",
+            "/%2E%2E/app.js",
+            "/..%2Fapp.js",
+            "/js%2F..%2Fapp.js",
+        ];
+        for path in paths {
+            let response = client.get(path).dispatch();
+            assert_eq!(response.status(), Status::Forbidden, "{}", path);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Subresource Integrity (SRI) metadata, computed along with fingerprints.
+use crate::source::AssetSource;
+use rocket::serde::Deserialize;
+use rocket::tokio::io::AsyncReadExt;
+use sha2::{Digest, Sha256, Sha384, Sha512};
+use std::fmt;
+use std::io;
+
+/// A hash algorithm of `integrity` attributes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub enum HashAlgorithm {
+    #[serde(rename = "sha256")]
+    Sha256,
+    #[serde(rename = "sha384")]
+    Sha384,
+    #[serde(rename = "sha512")]
+    Sha512,
+}
+
+impl HashAlgorithm {
+    /// Algorithms used when `assets_integrity` isn't configured
+    pub(crate) const DEFAULT: [HashAlgorithm; 1] = [HashAlgorithm::Sha384];
+
+    /// The name prefixing hashes, e.g. `sha384` in `sha384-...`
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha384 => "sha384",
+            HashAlgorithm::Sha512 => "sha512",
+        }
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Hashes an asset, returning its SHA-256 digest (for fingerprinting) and its integrity metadata
+/// (e.g. `sha384-...`), one hash per algorithm
+///
+/// Every hash is computed in a single read of the asset, unless SHA-256 is the only one needed:
+/// the source's (possibly precomputed) digest is used then. Hashes are always of the identity
+/// (uncompressed) contents.
+pub(crate) async fn hash(
+    source: &dyn AssetSource,
+    path: &str,
+    algorithms: &[HashAlgorithm],
+) -> io::Result<([u8; 32], String)> {
+    let mut sha384 = algorithms
+        .contains(&HashAlgorithm::Sha384)
+        .then(Sha384::new);
+    let mut sha512 = algorithms
+        .contains(&HashAlgorithm::Sha512)
+        .then(Sha512::new);
+    let digest = match sha384.is_some() || sha512.is_some() {
+        false => source.digest(path).await?,
+        true => {
+            let mut sha256 = Sha256::new();
+            let mut reader = source.open(path).await?;
+            let mut buffer = vec![0; 8 * 1024];
+            loop {
+                let read = reader.read(&mut buffer).await?;
+                if read == 0 {
+                    break;
+                }
+                sha256.update(&buffer[..read]);
+                sha384.iter_mut().for_each(|h| h.update(&buffer[..read]));
+                sha512.iter_mut().for_each(|h| h.update(&buffer[..read]));
+            }
+            sha256.finalize().into()
+        }
+    };
+    let sha384 = sha384.map(|h| h.finalize().to_vec());
+    let sha512 = sha512.map(|h| h.finalize().to_vec());
+
+    let hashes = algorithms.iter().filter_map(|algorithm| {
+        let hash = match algorithm {
+            HashAlgorithm::Sha256 => &digest[..],
+            HashAlgorithm::Sha384 => sha384.as_deref()?,
+            HashAlgorithm::Sha512 => sha512.as_deref()?,
+        };
+        Some(format!("{}-{}", algorithm, base64(hash)))
+    });
+    let metadata = hashes.collect::<Vec<_>>().join(" ");
+    Ok((digest, metadata))
+}
+
+/// Standard (padded) base64, as used by integrity metadata
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |group, (i, &b)| group | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(group >> (18 - 6 * i)) as usize & 63] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemorySource;
+    use crate::testing::Recording;
+
+    #[test]
+    fn base64_is_padded() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[rocket::async_test]
+    async fn metadata_lists_every_algorithm() {
+        let script = "alert('Hello, world.');";
+        let mut source = MemorySource::new();
+        source.insert("app.js", script);
+        let digest: [u8; 32] = Sha256::digest(script).into();
+
+        let sha384 = "sha384-H8BRh8j48O9oYatfu5AZzq6A9RINhZO5H16dQZngK7T62em8MUt1FLm52t+eX6xO";
+        let algorithms = [HashAlgorithm::Sha384];
+        let hashes = hash(&source, "app.js", &algorithms).await.unwrap();
+        assert_eq!(hashes, (digest, sha384.to_string()));
+
+        let algorithms = [HashAlgorithm::Sha256, HashAlgorithm::Sha384];
+        let hashes = hash(&source, "app.js", &algorithms).await.unwrap();
+        let sha256 = format!("sha256-{}", base64(&digest));
+        assert_eq!(hashes, (digest, format!("{} {}", sha256, sha384)));
+
+        let hashes = hash(&source, "app.js", &[]).await.unwrap();
+        assert_eq!(hashes, (digest, String::new()));
+    }
+
+    #[rocket::async_test]
+    async fn assets_are_read_once() {
+        let mut memory = MemorySource::new();
+        memory.insert("app.js", "alert('Hello, world.');");
+        let expected = memory.digest("app.js").await.unwrap();
+        let source = Recording::new(memory);
+        let algorithm_sets: [&[HashAlgorithm]; 3] = [
+            &[],
+            &[HashAlgorithm::Sha256],
+            &[
+                HashAlgorithm::Sha256,
+                HashAlgorithm::Sha384,
+                HashAlgorithm::Sha512,
+            ],
+        ];
+        for algorithms in algorithm_sets {
+            source.clear();
+            let (digest, _) = hash(&source, "app.js", algorithms).await.unwrap();
+            assert_eq!(digest, expected);
+            assert_eq!(source.opened(), ["app.js"]);
+        }
+    }
+}
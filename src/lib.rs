@@ -6,12 +6,14 @@
 //! # Usage
 //!
 //!   1. Add your assets to the configurable `assets_dir` directory (default: `{rocket_root}/assets`).
-//!   2. Optionally configure the cache policy using `assets_max_age`
+//!   2. Optionally configure the cache policy using `assets_max_age`, or per pattern with
+//!      `assets_cache` rules (see [`AssetsConfig`])
 //!   2. Attach [`Assets::fairing()`] and return an [`Asset`] using [`Assets::open()`] (specifying
 //!      the relative file path):
-//! ```rust
-//! use assets_rocket_fairing::{Asset, Assets};
-//! 
+//! ```rust,no_run
+//! # #[macro_use] extern crate rocket;
+//! use rocket_assets_fairing::{Asset, Assets};
+//!
 //! #[rocket::main]
 //! async fn main() {
 //!    rocket::build()
@@ -20,131 +22,411 @@
 //!        .launch()
 //!        .await;
 //! }
-//! 
+//!
 //! #[get("/style.css")]
 //! async fn style(assets: &Assets) -> Option<Asset> {
 //!    assets.open("style.css").await.ok()
 //! }
 //! ```
-use normpath::PathExt;
+//!
+//! Alternatively, mount [`Assets::routes()`] to serve every asset under some path:
+//! ```rust,no_run
+//! # use rocket_assets_fairing::Assets;
+//! # #[rocket::main]
+//! # async fn main() {
+//! rocket::build()
+//!     .attach(Assets::fairing())
+//!     .mount("/assets", Assets::routes())
+//!     .launch()
+//!     .await;
+//! # }
+//! ```
+//!
+//! Paths given to [`Assets::open()`] are always resolved inside of the assets directory: absolute
+//! paths, `..` segments, NUL bytes and symlinks pointing outside of it are rejected with a
+//! [`PathError`], so it's safe to pass user supplied segments to it.
+//!
+//! `assets_dir` can also be a list of directories, in which case each asset is served from the
+//! first one containing it (so that e.g. a base theme's files can be overridden).
+//!
+//! Every file is hashed when igniting, and [`Assets::url()`] returns the URL of a fingerprinted
+//! name for it (e.g. `/assets/style.3f9a1c0b.css`), a name [`Assets::open()`] also accepts. Those
+//! are served with an immutable, one year long cache policy, while the original names keep using
+//! `assets_max_age`.
+//! [`Assets::integrity()`] returns their Subresource Integrity metadata (`sha384` by default, see
+//! `assets_integrity`).
+//! When `assets_reload` is enabled (the default in the debug profile) along with the `watch` feature,
+//! the directories are watched and fingerprints recomputed as files change.
+//!
+//! Assets can also be read from other backends implementing [`AssetSource`] (such as the
+//! [`MemorySource`]), attached with [`Assets::fairing_from_source()`]. With the `embed` feature,
+//! they can be embedded in the executable at build time (see the `embed` module).
 use rocket::{
-    error,
-    fairing::{self, Fairing, Info, Kind},
-    fs::NamedFile,
-    info, info_,
+    fairing::Fairing,
+    http::Status,
     outcome::IntoOutcome,
     request::{self, FromRequest, Request},
-    response::{self, Responder, Response},
-    Build, Orbit, Rocket,
+    tokio::sync::broadcast,
 };
 use std::io;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+mod asset;
+mod cache;
+mod collection;
+#[cfg(feature = "compress")]
+mod compression;
+mod conditional;
+mod config;
+mod decorate;
+#[cfg(feature = "embed")]
+pub mod embed;
+mod encoding;
+mod error;
+mod fairing;
+mod filter;
+mod glob;
+mod handler;
+mod integrity;
+mod live_reload;
+mod manifest;
+mod mime;
+mod range;
+mod resolve;
+mod source;
+#[cfg(any(feature = "tera", feature = "handlebars"))]
+mod templates;
+#[cfg(test)]
+mod testing;
+mod types;
+#[cfg(feature = "watch")]
+mod watch;
+pub use asset::Asset;
+use asset::{Representation, Variant};
+use cache::CachePolicy;
+pub use cache::CacheRule;
+pub use collection::{Collection, Named};
+#[cfg(feature = "compress")]
+use compression::Compressor;
+pub use config::AssetsConfig;
+pub use encoding::Encoding;
+pub use error::AssetError;
+use fairing::AssetsFairing;
+use filter::Filter;
+pub use handler::AssetsHandler;
+pub use integrity::HashAlgorithm;
+use manifest::Manifest;
+use mime::Mime;
+pub use resolve::PathError;
+pub use source::{AssetReader, AssetSource, FileSource, MemorySource, Metadata};
+use types::TypesConfig;
 
 /// The asset collection located in the configured folder
 pub struct Assets {
-    path: PathBuf,
-    cache_max_age: i32,
+    name: Option<String>,
+    /// Sources assets are looked up in, by priority
+    layers: Vec<Arc<dyn AssetSource>>,
+    cache_max_age: u32,
+    /// Cache policies by pattern, the first matching one being used
+    cache_rules: Vec<CacheRule>,
+    encodings: Vec<Encoding>,
+    #[cfg(feature = "compress")]
+    compressor: Option<Arc<Compressor>>,
+    /// Hidden assets (dotfiles and ignored paths)
+    filter: Filter,
+    /// Allowed and sandboxed types
+    types: TypesConfig,
+    /// Media types of assets
+    mime: Mime,
+    /// Algorithms of the integrity metadata
+    integrity: Vec<HashAlgorithm>,
+    /// Public URL assets are served under, without a trailing `/` (empty for the root)
+    base: String,
+    /// How other handlers' responses are decorated
+    decorate: decorate::Decorate,
+    manifest: Arc<RwLock<Manifest>>,
+    /// Whether to watch the directories for changes
+    reload: bool,
+    /// Whether the live reload routes are mounted
+    live_reload: bool,
+    /// Changes found by the watcher, for live reload subscribers
+    changes: broadcast::Sender<live_reload::Change>,
 }
 
 impl Assets {
     /// Returns the fairing to be attached
     pub fn fairing() -> impl Fairing {
-        AssetsFairing
+        AssetsFairing::new(None)
     }
-    /// Opens up a named asset file, returning an [`Asset`]
-    pub async fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Asset> {
-        let mut asset_path = self.path.clone();
-        asset_path.push(path);
-        let file = NamedFile::open(Path::new(&asset_path)).await?;
-        let cache_max_age = self.cache_max_age;
-        Ok(Asset {
-            file,
-            cache_max_age,
-        })
+    /// Returns the fairing for a named asset collection, configured through its own
+    /// `[default.assets.<name>]` table (with `dir`, `max_age`, `cache`, `encodings` and `compression` keys)
+    ///
+    /// Any amount of named collections can be attached (along with the default one), and picked
+    /// in routes through the [`Named`] request guard, or mounted with [`Assets::routes_named()`].
+    pub fn fairing_named<N: Into<String>>(name: N) -> impl Fairing {
+        AssetsFairing::new(Some(name.into()))
     }
-}
-#[rocket::async_trait]
-impl<'r> FromRequest<'r> for &'r Assets {
-    type Error = ();
-    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
-        req.rocket().state::<Assets>().or_forward(())
+    /// Returns the fairing serving assets embedded in the executable by [`include_assets!`],
+    /// instead of reading them from `assets_dir`
+    ///
+    /// Everything else (`assets_max_age`, `assets_encodings`, routes using [`Assets::open()`] and
+    /// [`Assets::routes()`]) works the same. See the [`embed`] module.
+    #[cfg(feature = "embed")]
+    pub fn fairing_embedded(files: &'static [embed::EmbeddedFile]) -> impl Fairing {
+        Assets::fairing_from_source(embed::EmbeddedSource::new(files))
     }
-}
-
-/// An asset that can be returned from a route
-pub struct Asset {
-    file: NamedFile,
-    cache_max_age: i32,
-}
-impl<'r> Responder<'r, 'static> for Asset {
-    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
-        let cache_control = format!("max-age={}", self.cache_max_age);
-        Response::build_from(self.file.respond_to(req)?)
-            .raw_header("Cache-control", cache_control)
-            .ok()
+    /// Returns the fairing serving assets from a custom [`AssetSource`], instead of `assets_dir`
+    ///
+    /// The rest of the configuration (`assets_max_age`, `assets_encodings`,
+    /// `assets_compression`) still applies.
+    pub fn fairing_from_source<S: AssetSource>(source: S) -> impl Fairing {
+        AssetsFairing::with_source(Arc::new(source))
     }
-}
+    /// Returns the fairing for the default collection, configured in code instead of through
+    /// Rocket's configuration (see [`AssetsConfig`])
+    pub fn fairing_with(config: AssetsConfig) -> impl Fairing {
+        AssetsFairing::with_config(None, config)
+    }
+    /// Returns the fairing for a named asset collection, configured in code instead of through
+    /// an `[assets.<name>]` table (see [`AssetsConfig`] and [`Assets::fairing_named()`])
+    pub fn fairing_named_with<N: Into<String>>(name: N, config: AssetsConfig) -> impl Fairing {
+        AssetsFairing::with_config(Some(name.into()), config)
+    }
+    /// Returns the fairing of `rocket_dyn_templates`' [`Template`]s (to attach instead of
+    /// `Template::fairing()`), with helpers resolving assets registered in its engines
+    ///
+    /// It must be attached after the collections' fairings. With the `tera` feature,
+    /// `asset_url(path="style.css")` returns the fingerprinted URL of an asset (see
+    /// [`Assets::url()`], e.g. `/assets/style.3f9a1c0b.css`),
+    /// `asset_integrity(path="app.js")` its [integrity](Assets::integrity()) and
+    /// `asset_inline(path="critical.css")` its contents (to be used with `| safe`). With the
+    /// `handlebars` feature, `{{asset_url "style.css"}}`, `{{asset_integrity "app.js"}}` and
+    /// `{{{asset_inline "critical.css"}}}` do the same. They all take an optional `collection`
+    /// argument naming a collection, and fail rendering when the asset doesn't exist.
+    ///
+    /// [`Template`]: rocket_dyn_templates::Template
+    #[cfg(any(feature = "tera", feature = "handlebars"))]
+    pub fn templates() -> impl Fairing {
+        templates::TemplatesFairing::new(Box::new(|_| ()))
+    }
+    /// Same as [`Assets::templates()`], also customizing the engines (as with
+    /// `Template::custom()`)
+    #[cfg(any(feature = "tera", feature = "handlebars"))]
+    pub fn templates_with<F>(customize: F) -> impl Fairing
+    where
+        F: Fn(&mut rocket_dyn_templates::Engines) + Send + Sync + 'static,
+    {
+        templates::TemplatesFairing::new(Box::new(customize))
+    }
+    /// Returns a handler serving every asset, to be mounted wherever you want:
+    /// `rocket.mount("/assets", Assets::routes())`
+    ///
+    /// Requests for missing assets are forwarded, so other routes can still match them. The rank
+    /// of the route can be changed with [`AssetsHandler::rank()`].
+    pub fn routes() -> AssetsHandler {
+        AssetsHandler::new(None)
+    }
+    /// Returns a handler serving every asset of a named collection, see [`Assets::routes()`]
+    pub fn routes_named<N: Into<String>>(name: N) -> AssetsHandler {
+        AssetsHandler::new(Some(name.into()))
+    }
+    /// Opens up a named asset file, returning an [`Asset`]
+    ///
+    /// Both the original (`style.css`) and fingerprinted (`style.3f9a1c0b.css`, see
+    /// [`Assets::url()`]) names are accepted. Fingerprinted assets are served with an immutable
+    /// cache policy, unless they changed since they were hashed.
+    ///
+    /// Precompressed sidecar files (e.g. `style.css.br`) for the allowed `assets_encodings` are
+    /// picked up as well, and served instead when the client accepts their encoding. If
+    /// `assets_compression` is enabled, compressible assets without sidecars are compressed on the
+    /// fly instead.
+    ///
+    /// Missing assets, and paths refused because they escape the assets directory (see
+    /// [`PathError`]), are told apart by the returned [`AssetError`], which can be responded with.
+    pub async fn open<P: AsRef<Path>>(&self, path: P) -> Result<Asset, AssetError> {
+        let path = path.as_ref();
+        let error = |e: io::Error| AssetError::new(path.to_string_lossy().into_owned(), e);
 
-struct AssetsFairing;
-#[rocket::async_trait]
-impl Fairing for AssetsFairing {
-    fn info(&self) -> Info {
-        let kind = Kind::Response | Kind::Ignite | Kind::Liftoff;
-        Info {
-            kind,
-            name: "Static Assets",
+        let requested = resolve::normalize(path).map_err(|e| error(e.into()))?;
+        let requested =
+            manifest::url_path(&requested).ok_or_else(|| error(io::ErrorKind::NotFound.into()))?;
+        // Fingerprinted names are mapped back, along with the version their hash is of
+        let original = self
+            .manifest()
+            .original(&requested)
+            .map(|(original, entry)| (original.to_string(), entry.version.clone()));
+        let (relative, hashed) = match original {
+            Some((original, version)) => (original, Some(version)),
+            None => (requested, None),
+        };
+        if !self.filter.allows(&relative) {
+            let path = path.to_string_lossy().into_owned();
+            return Err(AssetError::Hidden { path });
         }
+        let asset = self
+            .open_in(&self.layers, relative.clone(), hashed.as_deref())
+            .await
+            .map_err(error)?;
+        self.types.apply(&relative, asset).ok_or_else(|| {
+            let path = path.to_string_lossy().into_owned();
+            AssetError::Disallowed { path }
+        })
     }
+    /// Opens an asset from the given layers, with this collection's settings
+    async fn open_in(
+        &self,
+        layers: &[Arc<dyn AssetSource>],
+        relative: String,
+        hashed: Option<&str>,
+    ) -> io::Result<Asset> {
+        let (layer, metadata) = lookup(layers, &relative).await?;
+        // Only immutable while the contents are still the ones the fingerprint was computed from
+        let immutable = hashed == Some(metadata.version.as_str());
+        let source = &layers[layer];
+        let mut identity = Representation::open(&**source, &relative, metadata).await?;
+        let given = identity.content_type.take();
+        identity.content_type = (self.mime)
+            .guess(&relative, given, &mut *identity.contents)
+            .await?;
+        #[cfg(feature = "compress")]
+        let compressor = self
+            .compressor
+            .as_ref()
+            .filter(|c| c.should_compress(identity.content_type.as_ref(), identity.len));
 
-    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
-        use rocket::figment::value::magic::RelativePathBuf;
-
-        let configured_dir = rocket
-            .figment()
-            .extract_inner::<RelativePathBuf>("assets_dir")
-            .map(|path| path.relative());
-
-        let relative_path = match configured_dir {
-            Ok(dir) => dir,
-            Err(e) if e.missing() => "assets/".into(),
-            Err(e) => {
-                rocket::config::pretty_print_error(e);
-                return Err(rocket);
+        let mut variants = Vec::new();
+        for &encoding in &self.encodings {
+            let sidecar = format!("{}.{}", relative, encoding.extension());
+            // Missing (or escaping) sidecars are simply not offered. They're only looked up in
+            // the same layer, so that overriding a file doesn't serve stale sidecars
+            if let Ok(metadata) = source.metadata(&sidecar).await {
+                let source = source.clone();
+                let path = sidecar;
+                let metadata = Box::new(metadata);
+                variants.push((
+                    encoding,
+                    Variant::Sidecar {
+                        source,
+                        path,
+                        metadata,
+                    },
+                ));
+                continue;
             }
-        };
-
-        let path = match relative_path.normalize() {
-            Ok(path) => path.into_path_buf(),
-            Err(e) => {
-                error!(
-                    "Invalid assets directory '{}': {}.",
-                    relative_path.display(),
-                    e
-                );
-                return Err(rocket);
+            #[cfg(feature = "compress")]
+            if let Some(compressor) = compressor.filter(|_| Compressor::supports(encoding)) {
+                let compressor = compressor.clone();
+                let source = source.clone();
+                let path = relative.clone();
+                variants.push((
+                    encoding,
+                    Variant::Compressed {
+                        compressor,
+                        source,
+                        path,
+                    },
+                ));
             }
-        };
-
-        let cache_max_age = rocket
-            .figment()
-            .extract_inner::<i32>("assets_max_age")
-            .unwrap_or(86400);
+        }
 
-        Ok(rocket.manage(Assets {
-            path,
-            cache_max_age,
-        }))
+        let cache = match immutable {
+            true => CachePolicy::fingerprinted(),
+            false => cache::policy(&self.cache_rules, &relative)
+                .cloned()
+                .unwrap_or_else(|| CachePolicy::max_age(self.cache_max_age)),
+        };
+        Ok(Asset {
+            identity,
+            variants,
+            cache,
+            headers: Vec::new(),
+        })
     }
+    /// Returns the URL of an asset's fingerprinted name, which embeds a hash of its contents
+    /// (e.g. `/assets/style.3f9a1c0b.css` for `style.css`), or `None` if the asset wasn't found
+    /// when igniting
+    ///
+    /// URLs are under `assets_base_url` when configured, which may be a path or an absolute URL
+    /// (e.g. of a CDN), and otherwise under the path [`Assets::routes()`] is mounted at (the root
+    /// if it isn't). Use it when generating links, so that assets can be cached forever by
+    /// clients.
+    pub fn url<P: AsRef<Path>>(&self, path: P) -> Option<String> {
+        let relative = manifest::url_path(&resolve::normalize(path.as_ref()).ok()?)?;
+        let manifest = self.manifest();
+        let fingerprinted = manifest.fingerprinted(&relative)?;
+        Some(manifest::public_url(&self.base, fingerprinted))
+    }
+    /// Returns the Subresource Integrity metadata of an asset (e.g. `sha384-...`), to be used as
+    /// the `integrity` attribute of `<script>` and `<link>` elements, or `None` if the asset
+    /// wasn't found when igniting (or `assets_integrity` is empty)
+    ///
+    /// Hashes are of the uncompressed contents, so they match whichever encoding is served, and
+    /// are kept up to date with hot reloads.
+    pub fn integrity<P: AsRef<Path>>(&self, path: P) -> Option<String> {
+        let relative = manifest::url_path(&resolve::normalize(path.as_ref()).ok()?)?;
+        self.manifest().integrity(&relative).map(String::from)
+    }
+    /// Returns the directory (layer) a path resolves to: the first of the configured
+    /// `assets_dir`s containing it
+    ///
+    /// Assets from sources without a directory (see [`AssetSource::root()`]) result in an
+    /// [`io::ErrorKind::Unsupported`] error.
+    pub async fn layer<P: AsRef<Path>>(&self, path: P) -> io::Result<&Path> {
+        let relative = resolve::normalize(path.as_ref())?;
+        let relative = manifest::url_path(&relative)
+            .filter(|relative| self.filter.allows(relative))
+            .ok_or(io::ErrorKind::NotFound)?;
+        let (layer, _) = lookup(&self.layers, &relative).await?;
+        self.layers[layer].root().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "asset source isn't stored in a directory",
+            )
+        })
+    }
+    fn manifest(&self) -> RwLockReadGuard<'_, Manifest> {
+        self.manifest.read().expect("manifest lock poisoned")
+    }
+    /// Returns the path of the live reload client script (e.g. `/__assets/live-reload.js`), if
+    /// `assets_live_reload` is enabled
+    ///
+    /// Include it in pages during development: stylesheets are swapped as they change, and the
+    /// page is reloaded when other assets do.
+    pub fn live_reload_script(&self) -> Option<String> {
+        match self.live_reload {
+            true => Some(format!("{}/live-reload.js", live_reload::base(self.name()))),
+            false => None,
+        }
+    }
+    /// Name of the collection, or `None` for the default one
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
 
-    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
-        use rocket::{figment::Source, log::PaintExt, yansi::Paint};
-
-        let state = rocket
-            .state::<Assets>()
-            .expect("Template AssetsContext registered in on_ignite");
+/// Looks up a relative path in every layer, by priority
+async fn lookup(layers: &[Arc<dyn AssetSource>], relative: &str) -> io::Result<(usize, Metadata)> {
+    for (layer, source) in layers.iter().enumerate() {
+        match source.metadata(relative).await {
+            Ok(metadata) => return Ok((layer, metadata)),
+            // Directories don't shadow files in later layers either
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::ErrorKind::NotFound.into())
+}
 
-        info!("{}{}:", Paint::emoji("📐 "), Paint::magenta("Assets"));
-        info_!("directory: {}", Paint::white(Source::from(&*state.path)));
-        info_!("cache max age: {}", Paint::white(state.cache_max_age));
+/// Request guard for the default collection
+///
+/// If [`Assets::fairing()`] (or one of its variants) isn't attached, the guard forwards with a
+/// `500 Internal Server Error` status: lower-ranked routes are tried, and the `500` catcher runs
+/// if none matches.
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'r Assets {
+    type Error = ();
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        collection::find(req.rocket(), None).or_forward(Status::InternalServerError)
     }
 }
@@ -0,0 +1,39 @@
+// Reloads stylesheets (or the whole page) when assets change, see `assets_live_reload`.
+(function () {
+  var script = document.currentScript;
+  var events = new EventSource(script.src.replace(/\.js(\?.*)?$/, ""));
+  // Fingerprinted names look like `style.3f9a1c0b.css`
+  var fingerprint = /\.[0-9a-f]{8}(\.[^./]*)?$/;
+
+  // Points the stylesheets linking to `path` to its new version, returning whether there was any
+  function reloadStylesheets(path, fingerprinted) {
+    var links = document.querySelectorAll('link[rel="stylesheet"]');
+    var reloaded = false;
+    Array.prototype.forEach.call(links, function (link) {
+      var url = new URL(link.href);
+      var name = url.pathname.replace(fingerprint, "$1");
+      if (name.slice(-path.length - 1) !== "/" + path) {
+        return;
+      }
+      var prefix = name.slice(0, name.length - path.length);
+      var isFingerprinted = fingerprint.test(url.pathname);
+      url.pathname = prefix + (isFingerprinted ? fingerprinted : path);
+      url.searchParams.set("live-reload", Date.now());
+      link.href = url.toString();
+      reloaded = true;
+    });
+    return reloaded;
+  }
+
+  // Each event's data holds the changed path, and its new fingerprinted path (empty if removed)
+  events.addEventListener("change", function (event) {
+    var lines = event.data.split("\n");
+    var path = lines[0];
+    var fingerprinted = lines[1];
+
+    var isStylesheet = /\.css$/.test(path) && fingerprinted;
+    if (!isStylesheet || !reloadStylesheets(path, fingerprinted)) {
+      window.location.reload();
+    }
+  });
+})();
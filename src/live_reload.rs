@@ -0,0 +1,228 @@
+//! Live reloading: a Server-Sent Events route broadcasting asset changes, and its client script.
+use crate::collection;
+use crate::source::{AssetSource, MemorySource};
+use rocket::http::{ContentType, Method, Status};
+use rocket::response::stream::{Event, EventStream};
+use rocket::route::{Handler, Outcome, Route};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::{error_, Data, Request};
+use std::sync::{Arc, OnceLock};
+
+/// Path the routes are mounted at, followed by the collection's name for named ones
+pub(crate) const BASE: &str = "/__assets";
+/// Name of the client script
+const SCRIPT: &str = "live-reload.js";
+
+/// Amount of changes kept for slow live reload subscribers
+pub(crate) const CHANGES_CAPACITY: usize = 64;
+
+/// A changed asset, as broadcast to live reload subscribers
+#[derive(Debug, Clone)]
+pub(crate) struct Change {
+    /// Relative path of the asset
+    pub path: String,
+    /// Its new fingerprinted path, `None` if it was removed
+    pub fingerprinted: Option<String>,
+}
+
+/// Base path of a collection's live reload routes
+pub(crate) fn base(collection: Option<&str>) -> String {
+    match collection {
+        None => BASE.to_string(),
+        Some(name) => format!("{}/{}", BASE, name),
+    }
+}
+
+/// The client script, served as an asset of its own source
+fn script() -> &'static [Arc<dyn AssetSource>] {
+    static SOURCE: OnceLock<[Arc<dyn AssetSource>; 1]> = OnceLock::new();
+    SOURCE.get_or_init(|| {
+        let mut source = MemorySource::new();
+        source.insert_typed(
+            SCRIPT,
+            ContentType::JavaScript,
+            include_str!("live-reload.js"),
+        );
+        [Arc::new(source)]
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Endpoint {
+    /// The event stream
+    Events,
+    /// The client script
+    Script,
+}
+
+/// Handler for the live reload routes of a collection
+#[derive(Debug, Clone)]
+pub(crate) struct LiveReload {
+    collection: Option<String>,
+    endpoint: Endpoint,
+}
+
+impl LiveReload {
+    /// The routes to be mounted at [`base()`]: `/live-reload` (events) and `/live-reload.js`
+    pub fn routes(collection: Option<String>) -> Vec<Route> {
+        let name = match &collection {
+            None => "Assets live reload".to_string(),
+            Some(name) => format!("Assets live reload ({})", name),
+        };
+        let routes = [
+            ("/live-reload", Endpoint::Events),
+            ("/live-reload.js", Endpoint::Script),
+        ];
+        routes
+            .iter()
+            .map(|&(path, endpoint)| {
+                let handler = LiveReload {
+                    collection: collection.clone(),
+                    endpoint,
+                };
+                let mut route = Route::new(Method::Get, path, handler);
+                route.name = Some(name.clone().into());
+                route
+            })
+            .collect()
+    }
+}
+
+#[rocket::async_trait]
+impl Handler for LiveReload {
+    async fn handle<'r>(&self, req: &'r Request<'_>, data: Data<'r>) -> Outcome<'r> {
+        let assets = match collection::find(req.rocket(), self.collection.as_deref()) {
+            Some(assets) => assets,
+            None => {
+                error_!("Live reload routes mounted without their `Assets` collection.");
+                return Outcome::forward(data, Status::InternalServerError);
+            }
+        };
+
+        match self.endpoint {
+            Endpoint::Script => match assets.open_in(script(), SCRIPT.into(), None).await {
+                Ok(asset) => Outcome::from(req, asset),
+                Err(e) => {
+                    error_!("Failed to open the live reload script: {}.", e);
+                    Outcome::forward(data, Status::InternalServerError)
+                }
+            },
+            Endpoint::Events => {
+                let mut changes = assets.changes.subscribe();
+                let mut shutdown = req.rocket().shutdown();
+                let stream = EventStream! {
+                    loop {
+                        let change = select! {
+                            change = changes.recv() => change,
+                            _ = &mut shutdown => break,
+                        };
+                        match change {
+                            Ok(change) => yield event(change),
+                            // Some changes were missed, reload everything
+                            Err(RecvError::Lagged(_)) => yield Event::data("\n").event("change"),
+                            Err(RecvError::Closed) => break,
+                        }
+                    }
+                };
+                Outcome::from(req, stream)
+            }
+        }
+    }
+}
+
+/// A `change` event, holding the changed path and its new fingerprinted path on separate lines
+fn event(change: Change) -> Event {
+    let fingerprinted = change.fingerprinted.unwrap_or_default();
+    Event::data(format!("{}\n{}", change.path, fingerprinted)).event("change")
+}
+
+#[cfg(all(test, feature = "watch"))]
+mod tests {
+    use super::*;
+    use crate::{Assets, AssetsConfig};
+    use rocket::local::asynchronous::Client;
+    use rocket::tokio::io::AsyncReadExt;
+    use rocket::tokio::time::{timeout, Duration};
+    use tempfile::TempDir;
+
+    async fn client(profile: &str) -> (Client, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AssetsConfig::new().dir(dir.path()).live_reload(true);
+        let figment = rocket::Config::figment().select(profile);
+        let rocket = rocket::custom(figment).attach(Assets::fairing_with(config));
+        (Client::tracked(rocket).await.expect("valid rocket"), dir)
+    }
+
+    #[rocket::async_test]
+    async fn script_is_served() {
+        let (client, _dir) = client("debug").await;
+        let assets = client.rocket().state::<Assets>().unwrap();
+        let script = assets.live_reload_script().unwrap();
+        assert_eq!(script, "/__assets/live-reload.js");
+
+        let response = client.get(script).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JavaScript));
+        let body = response.into_string().await.unwrap();
+        assert_eq!(body, include_str!("live-reload.js"));
+    }
+
+    #[rocket::async_test]
+    async fn changes_are_streamed() {
+        let (client, _dir) = client("debug").await;
+        let assets = client.rocket().state::<Assets>().unwrap();
+
+        let mut response = client.get("/__assets/live-reload").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::EventStream));
+        let changes = [
+            ("css/style.css", Some("css/style.0123abcd.css")),
+            ("old.js", None),
+        ];
+        for (path, fingerprinted) in changes {
+            let change = Change {
+                path: path.to_string(),
+                fingerprinted: fingerprinted.map(String::from),
+            };
+            assets.changes.send(change).unwrap();
+        }
+
+        // Events are separated by blank lines, leaving out heartbeats (comments)
+        let parse = |body: &str| {
+            let events = body.split("\n\n").map(|event| {
+                let lines = event.lines().filter(|line| !line.starts_with(':'));
+                lines.collect::<Vec<_>>().join("\n")
+            });
+            events.filter(|event| !event.is_empty()).collect::<Vec<_>>()
+        };
+        let mut body = String::new();
+        let mut buffer = [0; 1024];
+        while !body.ends_with("\n\n") || parse(&body).len() < changes.len() {
+            let read = timeout(Duration::from_secs(10), response.read(&mut buffer));
+            let read = read.await.expect("changes weren't streamed").unwrap();
+            assert_ne!(read, 0, "the stream ended");
+            body.push_str(std::str::from_utf8(&buffer[..read]).unwrap());
+        }
+        let events = parse(&body);
+        assert_eq!(
+            events,
+            [
+                "event:change\ndata:css/style.css\ndata:css/style.0123abcd.css",
+                "event:change\ndata:old.js\ndata:",
+            ]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn routes_are_not_mounted_in_release() {
+        let (client, _dir) = client("release").await;
+        let assets = client.rocket().state::<Assets>().unwrap();
+        assert_eq!(assets.live_reload_script(), None);
+
+        let response = client.get("/__assets/live-reload.js").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.get("/__assets/live-reload").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,239 @@
+//! Content hashed (fingerprinted) file names and integrity metadata, computed when igniting.
+use crate::filter::Filter;
+use crate::integrity::{self, HashAlgorithm};
+use crate::source::AssetSource;
+use rocket::http::RawStr;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Amount of hex characters from the content hash used in fingerprinted names
+const FINGERPRINT_LEN: usize = 8;
+
+/// A file found when walking the assets directories
+#[derive(Debug)]
+pub(crate) struct Entry {
+    /// Fingerprinted relative path
+    pub fingerprinted: String,
+    /// Subresource Integrity metadata (e.g. `sha384-...`), empty without algorithms
+    pub integrity: String,
+    /// Version of the contents when they were hashed: the fingerprinted name only matches the
+    /// contents for as long as it's current
+    pub version: String,
+    /// Indices of the layers containing this file, the first one being the one it's served from
+    pub layers: Vec<usize>,
+}
+
+/// Mapping between relative asset paths and their fingerprinted names
+#[derive(Debug, Default)]
+pub(crate) struct Manifest {
+    /// Relative paths to their entries
+    entries: HashMap<String, Entry>,
+    /// Fingerprinted relative paths back to the original ones
+    originals: HashMap<String, String>,
+}
+
+impl Manifest {
+    /// Lists every layer (by priority) and hashes every file in them, skipping hidden ones
+    ///
+    /// Files shadowed by a previous layer aren't hashed, just recorded.
+    pub async fn build(
+        layers: &[Arc<dyn AssetSource>],
+        filter: &Filter,
+        algorithms: &[HashAlgorithm],
+    ) -> io::Result<Self> {
+        let mut manifest = Manifest::default();
+
+        for (layer, source) in layers.iter().enumerate() {
+            let paths = source.paths().await?;
+            for relative in paths.into_iter().filter(|path| filter.allows(path)) {
+                match manifest.entries.get_mut(&relative) {
+                    Some(entry) => entry.layers.push(layer),
+                    None => {
+                        // Before hashing, so that changes made meanwhile make the version stale
+                        let version = source.metadata(&relative).await?.version;
+                        let (digest, integrity) =
+                            integrity::hash(&**source, &relative, algorithms).await?;
+                        manifest.insert(relative, layer, digest, integrity, version)
+                    }
+                }
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    fn insert(
+        &mut self,
+        relative: String,
+        layer: usize,
+        digest: [u8; 32],
+        integrity: String,
+        version: String,
+    ) {
+        let fingerprinted = fingerprint(&relative, &digest);
+        self.originals
+            .insert(fingerprinted.clone(), relative.clone());
+        self.entries.insert(
+            relative,
+            Entry {
+                fingerprinted,
+                integrity,
+                version,
+                layers: vec![layer],
+            },
+        );
+    }
+
+    pub fn entry(&self, path: &str) -> Option<&Entry> {
+        self.entries.get(path)
+    }
+
+    /// Looks up the fingerprinted name of a relative path (with `/` separators)
+    pub fn fingerprinted(&self, path: &str) -> Option<&str> {
+        self.entry(path).map(|e| e.fingerprinted.as_str())
+    }
+
+    /// Looks up the integrity metadata of a relative path, if any algorithm is configured
+    pub fn integrity(&self, path: &str) -> Option<&str> {
+        let entry = self.entry(path)?;
+        Some(entry.integrity.as_str()).filter(|integrity| !integrity.is_empty())
+    }
+
+    /// Maps a fingerprinted relative path back to the original one, along with its entry
+    pub fn original(&self, fingerprinted: &str) -> Option<(&str, &Entry)> {
+        let original = self.originals.get(fingerprinted)?;
+        Some((original.as_str(), self.entry(original)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Iterates over all entries, by relative path
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &Entry)> {
+        self.entries
+            .iter()
+            .map(|(path, entry)| (path.as_str(), entry))
+    }
+}
+
+/// Converts a relative path to a `/` separated string, as used in URLs and as manifest keys
+pub(crate) fn url_path(path: &Path) -> Option<String> {
+    let segments = path
+        .components()
+        .map(|c| c.as_os_str().to_str())
+        .collect::<Option<Vec<_>>>()?;
+    Some(segments.join("/"))
+}
+
+/// Joins a base URL (without a trailing `/`) and a relative path, percent-encoding its segments
+pub(crate) fn public_url(base: &str, path: &str) -> String {
+    let segments = path
+        .split('/')
+        .map(|segment| RawStr::new(segment).percent_encode().to_string());
+    format!("{}/{}", base, segments.collect::<Vec<_>>().join("/"))
+}
+
+/// Inserts the hash before the (last) extension: `css/style.css` -> `css/style.3f9a1c0b.css`
+fn fingerprint(path: &str, digest: &[u8]) -> String {
+    let hash: String = hex(digest).chars().take(FINGERPRINT_LEN).collect();
+    let name_start = path.rfind('/').map_or(0, |i| i + 1);
+
+    match path[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let (stem, extension) = path.split_at(name_start + dot);
+            format!("{}.{}{}", stem, hash, extension)
+        }
+        _ => format!("{}.{}", path, hash),
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Assets, AssetsConfig};
+    use rocket::local::asynchronous::Client;
+    use std::fs;
+
+    const DIGEST: [u8; 32] = [0xab; 32];
+
+    #[test]
+    fn fingerprints_go_before_the_last_extension() {
+        assert_eq!(fingerprint("style.css", &DIGEST), "style.abababab.css");
+        assert_eq!(
+            fingerprint("js/app.min.js", &DIGEST),
+            "js/app.min.abababab.js"
+        );
+        assert_eq!(fingerprint("LICENSE", &DIGEST), "LICENSE.abababab");
+        assert_eq!(fingerprint(".env", &DIGEST), ".env.abababab");
+        assert_eq!(
+            fingerprint("fonts/.hidden", &DIGEST),
+            "fonts/.hidden.abababab"
+        );
+        // Dots in directory names aren't extensions
+        assert_eq!(fingerprint("v1.2/README", &DIGEST), "v1.2/README.abababab");
+        assert_eq!(
+            fingerprint(".well-known/security.txt", &DIGEST),
+            ".well-known/security.abababab.txt"
+        );
+    }
+
+    #[test]
+    fn paths_are_joined_with_slashes() {
+        let path = Path::new("css").join("fonts").join("icons.woff2");
+        assert_eq!(url_path(&path).unwrap(), "css/fonts/icons.woff2");
+        assert_eq!(url_path(Path::new(".env")).unwrap(), ".env");
+        assert_eq!(url_path(Path::new("")).unwrap(), "");
+    }
+
+    #[test]
+    fn public_urls_are_percent_encoded() {
+        assert_eq!(public_url("", "css/style.css"), "/css/style.css");
+        assert_eq!(
+            public_url("/static", "my docs/a&b.pdf"),
+            "/static/my%20docs/a%26b.pdf"
+        );
+        let cdn = "https://cdn.example.com/static";
+        assert_eq!(
+            public_url(cdn, ".env"),
+            "https://cdn.example.com/static/.env"
+        );
+    }
+
+    #[rocket::async_test]
+    async fn changed_assets_are_not_immutable() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("app.min.js"), "console.log('v1');").unwrap();
+        let config = AssetsConfig::new().dir(dir.path()).reload(false);
+        let rocket = rocket::build()
+            .attach(Assets::fairing_with(config))
+            .mount("/", Assets::routes());
+        let client = Client::tracked(rocket).await.unwrap();
+        let url = client
+            .rocket()
+            .state::<Assets>()
+            .unwrap()
+            .url("app.min.js")
+            .unwrap();
+
+        let response = client.get(&url).dispatch().await;
+        let cache_control = response.headers().get_one("Cache-Control").unwrap();
+        assert!(cache_control.contains("immutable"), "{}", cache_control);
+
+        // Without reloading, the fingerprint is of the previous contents
+        fs::write(dir.path().join("app.min.js"), "console.log('version 2');").unwrap();
+        let response = client.get(&url).dispatch().await;
+        let cache_control = response.headers().get_one("Cache-Control").unwrap();
+        assert_eq!(cache_control, "max-age=86400");
+        assert_eq!(
+            response.into_string().await.unwrap(),
+            "console.log('version 2');"
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Media types of assets: configured extensions, known ones, and content sniffing.
+use crate::source::AssetReader;
+use rocket::http::ContentType;
+use rocket::serde::{de, Deserialize, Deserializer};
+use rocket::tokio::io::{AsyncReadExt, AsyncSeekExt};
+use std::collections::HashMap;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+
+/// Extensions missing from Rocket's known media types
+const EXTRA_TYPES: &[(&str, &str)] = &[
+    ("webmanifest", "application/manifest+json"),
+    ("map", "application/json"),
+];
+/// Amount of bytes looked at when sniffing
+const SNIFF_LEN: u64 = 512;
+
+/// Media types by (lowercase) extension, as configured in `assets_mime_types`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MimeTypes(HashMap<String, ContentType>);
+
+impl MimeTypes {
+    pub fn insert(&mut self, extension: &str, content_type: ContentType) {
+        let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+        self.0.insert(extension, content_type);
+    }
+}
+
+impl<'de> Deserialize<'de> for MimeTypes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut types = MimeTypes::default();
+        for (extension, name) in HashMap::<String, String>::deserialize(deserializer)? {
+            let content_type = ContentType::parse_flexible(&name).ok_or_else(|| {
+                de::Error::invalid_value(de::Unexpected::Str(&name), &"a media type")
+            })?;
+            types.insert(&extension, content_type);
+        }
+        Ok(types)
+    }
+}
+
+/// Guesses the media type of assets
+#[derive(Debug, Clone)]
+pub(crate) struct Mime {
+    /// Configured media types by extension, taking precedence over everything else
+    pub types: MimeTypes,
+    /// Charset given to text types without one, if any
+    pub charset: Option<String>,
+    /// Whether to sniff the type of assets without an extension
+    pub sniff: bool,
+}
+
+impl Mime {
+    /// The media type of an asset: configured for its extension, given by its source, known for
+    /// its extension, or sniffed from its first bytes when it has no extension
+    ///
+    /// Sniffing reads from `contents`, seeking back to the start afterwards.
+    pub async fn guess(
+        &self,
+        path: &str,
+        given: Option<ContentType>,
+        contents: &mut dyn AssetReader,
+    ) -> io::Result<Option<ContentType>> {
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        let configured = extension
+            .as_deref()
+            .and_then(|extension| self.types.0.get(extension))
+            .cloned();
+
+        let content_type = match (configured.or(given), extension) {
+            (Some(content_type), _) => Some(content_type),
+            (None, Some(extension)) => known(&extension),
+            (None, None) if self.sniff => Some(sniff(contents).await?),
+            (None, None) => None,
+        };
+        Ok(content_type.map(|content_type| self.with_charset(content_type)))
+    }
+
+    /// Adds the configured charset to text types without one
+    fn with_charset(&self, content_type: ContentType) -> ContentType {
+        match &self.charset {
+            Some(charset)
+                if content_type.top() == "text" && content_type.param("charset").is_none() =>
+            {
+                content_type.with_params(("charset", charset.clone()))
+            }
+            _ => content_type,
+        }
+    }
+}
+
+/// Media type known for an extension, without Rocket's charset (which is configurable)
+pub(crate) fn known(extension: &str) -> Option<ContentType> {
+    let extra = EXTRA_TYPES
+        .iter()
+        .find(|(known, _)| *known == extension)
+        .and_then(|(_, name)| ContentType::parse_flexible(name));
+    let content_type = extra.or_else(|| ContentType::from_extension(extension))?;
+    Some(ContentType::new(
+        content_type.top().to_string(),
+        content_type.sub().to_string(),
+    ))
+}
+
+/// Sniffs the type of an asset from its first bytes
+async fn sniff(contents: &mut dyn AssetReader) -> io::Result<ContentType> {
+    let mut head = Vec::new();
+    (&mut *contents)
+        .take(SNIFF_LEN)
+        .read_to_end(&mut head)
+        .await?;
+    contents.seek(SeekFrom::Start(0)).await?;
+    Ok(sniff_bytes(&head))
+}
+
+/// Recognizes common binary signatures, and text
+///
+/// Documents able to run scripts (HTML, SVG) are never sniffed, they're plain text instead.
+fn sniff_bytes(head: &[u8]) -> ContentType {
+    let signatures: &[(&[u8], ContentType)] = &[
+        (b"\x89PNG\r\n\x1a\n", ContentType::PNG),
+        (b"\xff\xd8\xff", ContentType::JPEG),
+        (b"GIF87a", ContentType::GIF),
+        (b"GIF89a", ContentType::GIF),
+        (b"%PDF-", ContentType::PDF),
+        (b"\0asm", ContentType::WASM),
+        (b"\x1f\x8b", ContentType::GZIP),
+        (b"PK\x03\x04", ContentType::ZIP),
+        (b"wOFF", ContentType::WOFF),
+        (b"wOF2", ContentType::WOFF2),
+        (b"OggS", ContentType::OGG),
+    ];
+    if let Some((_, content_type)) = signatures.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return content_type.clone();
+    }
+    if head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP") {
+        return ContentType::WEBP;
+    }
+
+    // The head may cut a character in half
+    let text = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    let control = head
+        .iter()
+        .any(|&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b));
+    match text && !control {
+        true => ContentType::new("text", "plain"),
+        false => ContentType::Binary,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn mime() -> Mime {
+        let mut types = MimeTypes::default();
+        types.insert(".TPL", ContentType::HTML);
+        types.insert("js", ContentType::new("application", "javascript"));
+        Mime {
+            types,
+            charset: Some("utf-8".into()),
+            sniff: true,
+        }
+    }
+
+    async fn guess(mime: &Mime, path: &str, contents: &[u8]) -> Option<String> {
+        let mut contents = Cursor::new(contents.to_vec());
+        let content_type = mime.guess(path, None, &mut contents).await.unwrap();
+        assert_eq!(contents.position(), 0, "contents should be rewound");
+        content_type.map(|content_type| content_type.to_string())
+    }
+
+    #[rocket::async_test]
+    async fn extensions_are_mapped() {
+        let mime = mime();
+        let guessed = [
+            ("page.tpl", "text/html; charset=utf-8"),
+            ("app.js", "application/javascript"),
+            ("app.mjs", "text/javascript; charset=utf-8"),
+            ("site.webmanifest", "application/manifest+json"),
+            ("app.js.map", "application/json"),
+            ("image.avif", "image/avif"),
+            ("module.wasm", "application/wasm"),
+        ];
+        for (path, expected) in guessed {
+            assert_eq!(guess(&mime, path, b"").await.as_deref(), Some(expected));
+        }
+        assert_eq!(guess(&mime, "file.unknown", b"").await, None);
+    }
+
+    #[rocket::async_test]
+    async fn charset_is_configurable() {
+        let mime = Mime {
+            charset: Some("iso-8859-1".into()),
+            ..mime()
+        };
+        let guessed = guess(&mime, "style.css", b"").await;
+        assert_eq!(guessed.as_deref(), Some("text/css; charset=iso-8859-1"));
+
+        let mime = Mime {
+            charset: None,
+            ..mime
+        };
+        assert_eq!(
+            guess(&mime, "style.css", b"").await.as_deref(),
+            Some("text/css")
+        );
+    }
+
+    #[rocket::async_test]
+    async fn extensionless_files_are_sniffed() {
+        let mime = mime();
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(
+            guess(&mime, "LICENSE", b"MIT License\n").await.as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(
+            guess(&mime, "logo", png).await.as_deref(),
+            Some("image/png")
+        );
+        assert_eq!(
+            guess(&mime, "page", b"<html><script>").await.as_deref(),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(
+            guess(&mime, "blob", b"\0\x01\x02").await.as_deref(),
+            Some("application/octet-stream")
+        );
+
+        let mime = Mime {
+            sniff: false,
+            ..mime
+        };
+        assert_eq!(guess(&mime, "LICENSE", b"MIT License\n").await, None);
+    }
+}
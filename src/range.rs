@@ -0,0 +1,482 @@
+//! Byte range requests (`Range`/`If-Range`) and `206 Partial Content` responses.
+use crate::conditional::Validators;
+use rocket::http::{ContentType, Method, Status};
+use rocket::request::Request;
+use rocket::response::Response;
+use rocket::tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+use std::collections::VecDeque;
+use std::io::{self, SeekFrom};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Requests asking for more ranges than this are served whole
+const MAX_RANGES: usize = 32;
+/// Size of the buffer ranges are read through
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// An inclusive range of bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    fn content_range(&self, total: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, total)
+    }
+}
+
+/// The ranges requested by a client
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Ranges {
+    Satisfiable(Vec<ByteRange>),
+    Unsatisfiable,
+}
+
+/// Returns the ranges to respond with, if the request asks for (and is eligible to) a partial
+/// response of a `len` bytes long representation
+///
+/// Ranges are ignored (and the whole representation should be sent) for non-`GET` requests,
+/// malformed `Range` headers and when `If-Range` doesn't match the current validators.
+pub(crate) fn requested(req: &Request<'_>, validators: &Validators, len: u64) -> Option<Ranges> {
+    if req.method() != Method::Get {
+        return None;
+    }
+    let range = req.headers().get_one("Range")?;
+
+    if let Some(if_range) = req.headers().get_one("If-Range") {
+        if !if_range_matches(if_range.trim(), validators) {
+            return None;
+        }
+    }
+
+    parse(range, len)
+}
+
+/// `If-Range` only matches strong entity tags or exact modification dates
+fn if_range_matches(if_range: &str, validators: &Validators) -> bool {
+    if if_range.starts_with('"') {
+        if_range == validators.etag
+    } else if if_range.starts_with("W/") {
+        false
+    } else {
+        let date = httpdate::parse_http_date(if_range).ok();
+        date.is_some() && date == validators.last_modified
+    }
+}
+
+/// Parses a `Range` header, returning `None` when it's malformed (or uses another unit)
+pub(crate) fn parse(header: &str, len: u64) -> Option<Ranges> {
+    let (unit, specs) = header.trim().split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (start, end) = spec.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+
+        let range = if start.is_empty() {
+            // Suffix range: the last `end` bytes
+            let suffix = end.parse::<u64>().ok()?;
+            match suffix.min(len) {
+                0 => None,
+                suffix => Some(ByteRange {
+                    start: len - suffix,
+                    end: len - 1,
+                }),
+            }
+        } else {
+            let start = start.parse::<u64>().ok()?;
+            let end = match end {
+                "" => None,
+                end => Some(end.parse::<u64>().ok()?),
+            };
+            if end.is_some_and(|end| end < start) {
+                return None;
+            }
+            match start < len {
+                true => Some(ByteRange {
+                    start,
+                    end: end.map_or(len - 1, |end| end.min(len - 1)),
+                }),
+                false => None,
+            }
+        };
+        ranges.extend(range);
+    }
+
+    if specs.split(',').all(|s| s.trim().is_empty()) || ranges.len() > MAX_RANGES {
+        return None;
+    }
+    match ranges.is_empty() {
+        true => Some(Ranges::Unsatisfiable),
+        false => Some(Ranges::Satisfiable(ranges)),
+    }
+}
+
+/// Builds the response for the requested ranges of `body`: a `206 Partial Content` (using
+/// `multipart/byteranges` for multiple ranges) or a `416 Range Not Satisfiable`
+pub(crate) fn respond<R>(
+    body: R,
+    len: u64,
+    ranges: Ranges,
+    content_type: Option<ContentType>,
+) -> Response<'static>
+where
+    R: AsyncRead + AsyncSeek + Unpin + Send + 'static,
+{
+    let mut response = Response::new();
+    response.set_raw_header("Accept-Ranges", "bytes");
+
+    let ranges = match ranges {
+        Ranges::Satisfiable(ranges) => ranges,
+        Ranges::Unsatisfiable => {
+            response.set_status(Status::RangeNotSatisfiable);
+            response.set_raw_header("Content-Range", format!("bytes */{}", len));
+            return response;
+        }
+    };
+    response.set_status(Status::PartialContent);
+
+    if let [range] = ranges[..] {
+        response.set_raw_header("Content-Range", range.content_range(len));
+        if let Some(content_type) = content_type {
+            response.set_header(content_type);
+        }
+        let segments = vec![Segment::Range(range)];
+        response.set_sized_body(range.len() as usize, RangeBody::new(body, segments));
+        return response;
+    }
+
+    let boundary = boundary();
+    let mut segments = Vec::new();
+    for range in ranges {
+        let mut part = format!("\r\n--{}\r\n", boundary);
+        if let Some(content_type) = &content_type {
+            part.push_str(&format!("Content-Type: {}\r\n", content_type));
+        }
+        part.push_str(&format!(
+            "Content-Range: {}\r\n\r\n",
+            range.content_range(len)
+        ));
+        segments.push(Segment::Bytes(part.into_bytes()));
+        segments.push(Segment::Range(range));
+    }
+    segments.push(Segment::Bytes(
+        format!("\r\n--{}--\r\n", boundary).into_bytes(),
+    ));
+
+    let size = segments.iter().map(Segment::len).sum::<u64>();
+    let multipart = format!("multipart/byteranges; boundary={}", boundary);
+    response.set_raw_header("Content-Type", multipart);
+    response.set_sized_body(size as usize, RangeBody::new(body, segments));
+    response
+}
+
+/// A fresh multipart boundary
+fn boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("rocket-assets-{:08x}{:08x}", nanos, count)
+}
+
+/// A piece of a partial response body
+enum Segment {
+    /// Literal bytes (multipart headers and delimiters)
+    Bytes(Vec<u8>),
+    /// A range of the representation
+    Range(ByteRange),
+}
+
+impl Segment {
+    fn len(&self) -> u64 {
+        match self {
+            Segment::Bytes(bytes) => bytes.len() as u64,
+            Segment::Range(range) => range.len(),
+        }
+    }
+}
+
+enum State {
+    /// Waiting for the next segment
+    Next,
+    /// Writing out literal bytes, from the given position
+    Bytes(Vec<u8>, usize),
+    /// Seeking to the start of a range, which has the given length
+    Seeking(u64),
+    /// Reading a range, with the given amount of bytes left
+    Reading(u64),
+}
+
+/// A response body made of ranges of an underlying (seekable) reader, along with literal bytes
+pub(crate) struct RangeBody<R> {
+    inner: R,
+    segments: VecDeque<Segment>,
+    state: State,
+    buffer: Vec<u8>,
+}
+
+impl<R> RangeBody<R> {
+    fn new(inner: R, segments: Vec<Segment>) -> Self {
+        RangeBody {
+            inner,
+            segments: segments.into(),
+            state: State::Next,
+            buffer: vec![0; BUFFER_SIZE],
+        }
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncRead for RangeBody<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            match &mut this.state {
+                State::Next => match this.segments.pop_front() {
+                    None => return Poll::Ready(Ok(())),
+                    Some(Segment::Bytes(bytes)) => this.state = State::Bytes(bytes, 0),
+                    Some(Segment::Range(range)) => {
+                        Pin::new(&mut this.inner).start_seek(SeekFrom::Start(range.start))?;
+                        this.state = State::Seeking(range.len());
+                    }
+                },
+                State::Bytes(bytes, position) => {
+                    let remaining = &bytes[*position..];
+                    if remaining.is_empty() {
+                        this.state = State::Next;
+                        continue;
+                    }
+                    let amount = remaining.len().min(buf.remaining());
+                    buf.put_slice(&remaining[..amount]);
+                    *position += amount;
+                    return Poll::Ready(Ok(()));
+                }
+                State::Seeking(len) => {
+                    let len = *len;
+                    match Pin::new(&mut this.inner).poll_complete(cx) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(result) => result?,
+                    };
+                    this.state = State::Reading(len);
+                }
+                State::Reading(0) => this.state = State::Next,
+                State::Reading(remaining) => {
+                    let limit = (*remaining)
+                        .min(buf.remaining() as u64)
+                        .min(BUFFER_SIZE as u64) as usize;
+                    let mut limited = ReadBuf::new(&mut this.buffer[..limit]);
+                    match Pin::new(&mut this.inner).poll_read(cx, &mut limited) {
+                        Poll::Pending => return Poll::Pending,
+                        Poll::Ready(result) => result?,
+                    };
+                    let read = limited.filled();
+                    if read.is_empty() {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+
+                    buf.put_slice(read);
+                    *remaining -= read.len() as u64;
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+/// Partial bodies always have a preset size, so they're never seeked by Rocket
+impl<R: Unpin> AsyncSeek for RangeBody<R> {
+    fn start_seek(self: Pin<&mut Self>, _: SeekFrom) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "partial bodies can't be seeked",
+        ))
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "partial bodies can't be seeked",
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Header;
+    use rocket::local::blocking::Client;
+    use std::io::Cursor;
+
+    fn range(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    fn satisfiable(ranges: &[ByteRange]) -> Option<Ranges> {
+        Some(Ranges::Satisfiable(ranges.to_vec()))
+    }
+
+    fn validators() -> Validators {
+        Validators {
+            etag: "\"v1\"".into(),
+            last_modified: httpdate::parse_http_date("Wed, 21 Oct 2015 07:28:00 GMT").ok(),
+        }
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse("bytes=0-9", 100), satisfiable(&[range(0, 9)]));
+        assert_eq!(parse("bytes=90-", 100), satisfiable(&[range(90, 99)]));
+        assert_eq!(parse("bytes=-10", 100), satisfiable(&[range(90, 99)]));
+        assert_eq!(
+            parse(" Bytes = 0-0, 5-6 ,", 100),
+            satisfiable(&[range(0, 0), range(5, 6)])
+        );
+    }
+
+    #[test]
+    fn clamps_ranges() {
+        assert_eq!(parse("bytes=50-1000", 100), satisfiable(&[range(50, 99)]));
+        assert_eq!(parse("bytes=-1000", 100), satisfiable(&[range(0, 99)]));
+        // Unsatisfiable ranges are dropped as long as another one is satisfiable
+        assert_eq!(parse("bytes=0-1,100-200", 100), satisfiable(&[range(0, 1)]));
+        assert_eq!(parse("bytes=100-", 100), Some(Ranges::Unsatisfiable));
+        assert_eq!(parse("bytes=-0", 100), Some(Ranges::Unsatisfiable));
+    }
+
+    #[test]
+    fn empty_representations_are_unsatisfiable() {
+        assert_eq!(parse("bytes=0-", 0), Some(Ranges::Unsatisfiable));
+        assert_eq!(parse("bytes=0-9", 0), Some(Ranges::Unsatisfiable));
+        assert_eq!(parse("bytes=-10", 0), Some(Ranges::Unsatisfiable));
+    }
+
+    #[test]
+    fn ignores_invalid_ranges() {
+        assert_eq!(parse("bytes=9-0", 100), None);
+        assert_eq!(parse("bytes=0-1,9-0", 100), None);
+        assert_eq!(parse("items=0-9", 100), None);
+        assert_eq!(parse("0-9", 100), None);
+        assert_eq!(parse("bytes=", 100), None);
+        assert_eq!(parse("bytes=,", 100), None);
+        assert_eq!(parse("bytes=a-9", 100), None);
+        assert_eq!(parse("bytes=0-9-", 100), None);
+        assert_eq!(parse("bytes=-", 100), None);
+    }
+
+    #[test]
+    fn limits_range_count() {
+        let ranges = |count: u64| {
+            let specs = (0..count).map(|i| format!("{}-{}", i, i));
+            format!("bytes={}", specs.collect::<Vec<_>>().join(","))
+        };
+        let max = MAX_RANGES as u64;
+        let expected = (0..max).map(|i| range(i, i)).collect::<Vec<_>>();
+        assert_eq!(parse(&ranges(max), 100), satisfiable(&expected));
+        assert_eq!(parse(&ranges(max + 1), 100), None);
+    }
+
+    #[test]
+    fn if_range_requires_strong_matches() {
+        let validators = validators();
+        assert!(if_range_matches("\"v1\"", &validators));
+        assert!(!if_range_matches("\"v2\"", &validators));
+        assert!(!if_range_matches("W/\"v1\"", &validators));
+        assert!(if_range_matches(
+            "Wed, 21 Oct 2015 07:28:00 GMT",
+            &validators
+        ));
+        assert!(!if_range_matches(
+            "Wed, 21 Oct 2015 07:28:01 GMT",
+            &validators
+        ));
+        assert!(!if_range_matches("yesterday", &validators));
+
+        let undated = Validators {
+            last_modified: None,
+            ..validators
+        };
+        assert!(!if_range_matches("Wed, 21 Oct 2015 07:28:00 GMT", &undated));
+    }
+
+    #[test]
+    fn requested_honors_if_range() {
+        let client = Client::debug(rocket::build()).unwrap();
+        let validators = validators();
+        let requested = |if_range: Option<&'static str>| {
+            let mut request = client.get("/").header(Header::new("Range", "bytes=0-9"));
+            if let Some(if_range) = if_range {
+                request = request.header(Header::new("If-Range", if_range));
+            }
+            requested(request.inner(), &validators, 100)
+        };
+        assert_eq!(requested(None), satisfiable(&[range(0, 9)]));
+        assert_eq!(requested(Some(" \"v1\" ")), satisfiable(&[range(0, 9)]));
+        assert_eq!(requested(Some("\"v2\"")), None);
+        assert_eq!(requested(Some("W/\"v1\"")), None);
+
+        let head = client.head("/").header(Header::new("Range", "bytes=0-9"));
+        assert_eq!(super::requested(head.inner(), &validators, 100), None);
+    }
+
+    #[rocket::async_test]
+    async fn multipart_body_matches_its_size() {
+        let contents = (0..100u8).collect::<Vec<_>>();
+        let ranges = Ranges::Satisfiable(vec![range(0, 9), range(90, 99)]);
+        let mut response = respond(Cursor::new(contents), 100, ranges, Some(ContentType::CSS));
+        assert_eq!(response.status(), Status::PartialContent);
+
+        let content_type = response.headers().get_one("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        let size = response.body().preset_size().unwrap();
+        let body = response.body_mut().to_bytes().await.unwrap();
+        assert_eq!(body.len(), size);
+
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.starts_with(&format!("\r\n--{}\r\n", boundary)));
+        assert!(body.ends_with(&format!("\r\n--{}--\r\n", boundary)));
+        assert!(body.contains("Content-Type: text/css; charset=utf-8\r\n"));
+        assert!(body.contains("Content-Range: bytes 0-9/100\r\n"));
+        assert!(body.contains("Content-Range: bytes 90-99/100\r\n"));
+    }
+
+    #[rocket::async_test]
+    async fn single_range_body() {
+        let contents = (0..100u8).collect::<Vec<_>>();
+        let ranges = Ranges::Satisfiable(vec![range(10, 14)]);
+        let mut response = respond(Cursor::new(contents), 100, ranges, Some(ContentType::CSS));
+        assert_eq!(
+            response.headers().get_one("Content-Range"),
+            Some("bytes 10-14/100")
+        );
+        assert_eq!(response.content_type(), Some(ContentType::CSS));
+        let body = response.body_mut().to_bytes().await.unwrap();
+        assert_eq!(body, [10, 11, 12, 13, 14]);
+
+        let response = respond(Cursor::new(vec![]), 100, Ranges::Unsatisfiable, None);
+        assert_eq!(response.status(), Status::RangeNotSatisfiable);
+        assert_eq!(
+            response.headers().get_one("Content-Range"),
+            Some("bytes */100")
+        );
+    }
+}
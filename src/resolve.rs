@@ -0,0 +1,188 @@
+//! Resolution of (possibly user supplied) relative paths into the assets directory.
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Reason why a path was refused when resolving it into the assets directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// The path contains a parent directory (`..`) segment
+    ParentSegment,
+    /// The path is absolute (or has a drive/UNC prefix)
+    Absolute,
+    /// The path contains a NUL byte
+    NulByte,
+    /// The path resolves (e.g. through a symlink) to a location outside of the assets directory
+    Escapes,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            PathError::ParentSegment => "path contains a parent directory segment",
+            PathError::Absolute => "path is absolute",
+            PathError::NulByte => "path contains a NUL byte",
+            PathError::Escapes => "path escapes the assets directory",
+        };
+        f.write_str(reason)
+    }
+}
+
+impl Error for PathError {}
+
+impl From<PathError> for io::Error {
+    fn from(e: PathError) -> Self {
+        io::Error::new(io::ErrorKind::PermissionDenied, e)
+    }
+}
+
+/// Lexically checks a relative path, returning its normalized form (without `.` segments)
+pub(crate) fn normalize(path: &Path) -> Result<PathBuf, PathError> {
+    if path.to_string_lossy().contains('\0') {
+        return Err(PathError::NulByte);
+    }
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(segment) => normalized.push(segment),
+            Component::CurDir => {}
+            Component::ParentDir => return Err(PathError::ParentSegment),
+            Component::RootDir | Component::Prefix(_) => return Err(PathError::Absolute),
+        }
+    }
+    Ok(normalized)
+}
+
+/// Resolves `path` inside of `root`, following symlinks and making sure the result is still
+/// contained in `root`.
+///
+/// `root` is expected to already be canonical (as done when igniting the fairing).
+pub(crate) async fn resolve(root: &Path, path: &Path) -> io::Result<PathBuf> {
+    let joined = root.join(normalize(path)?);
+    let resolved = rocket::tokio::fs::canonicalize(&joined).await?;
+
+    if !resolved.starts_with(root) {
+        return Err(PathError::Escapes.into());
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    struct Fixture {
+        _dir: tempfile::TempDir,
+        root: PathBuf,
+    }
+
+    /// Creates `{tmp}/assets` with a couple of files, plus a `{tmp}/secret.txt` outside of it
+    fn fixture() -> Fixture {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+        let root = base.join("assets");
+
+        fs::create_dir_all(root.join("nested")).unwrap();
+        fs::write(root.join("style.css"), "body {}").unwrap();
+        fs::write(root.join("nested/app.js"), "alert(1)").unwrap();
+        fs::write(base.join("secret.txt"), "hunter2").unwrap();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::symlink;
+            symlink(base.join("secret.txt"), root.join("escape.txt")).unwrap();
+            symlink(&base, root.join("parent")).unwrap();
+            symlink(root.join("style.css"), root.join("nested/alias.css")).unwrap();
+        }
+
+        Fixture { _dir: dir, root }
+    }
+
+    fn rejection(result: io::Result<PathBuf>) -> PathError {
+        let e = result.expect_err("path should have been rejected");
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        *e.get_ref()
+            .and_then(|e| e.downcast_ref::<PathError>())
+            .expect("error should carry a PathError")
+    }
+
+    #[rocket::async_test]
+    async fn resolves_contained_paths() {
+        let f = fixture();
+        for path in [
+            "style.css",
+            "./style.css",
+            "nested/app.js",
+            "nested/./app.js",
+        ] {
+            let resolved = resolve(&f.root, Path::new(path)).await.unwrap();
+            assert!(resolved.starts_with(&f.root), "{}", path);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn rejects_parent_segments() {
+        let f = fixture();
+        for path in [
+            "..",
+            "../secret.txt",
+            "nested/../../secret.txt",
+            "nested/../style.css",
+            "./../assets/style.css",
+        ] {
+            let result = resolve(&f.root, Path::new(path)).await;
+            assert_eq!(rejection(result), PathError::ParentSegment, "{}", path);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn rejects_absolute_paths() {
+        let f = fixture();
+        let inside = f.root.join("style.css");
+        for path in [Path::new("/etc/passwd"), Path::new("/"), inside.as_path()] {
+            let result = resolve(&f.root, path).await;
+            assert_eq!(rejection(result), PathError::Absolute, "{}", path.display());
+        }
+    }
+
+    #[rocket::async_test]
+    async fn rejects_nul_bytes() {
+        let f = fixture();
+        for path in ["style.css\0", "style.css\0.png", "\0/etc/passwd"] {
+            let result = resolve(&f.root, Path::new(path)).await;
+            assert_eq!(rejection(result), PathError::NulByte, "{:?}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[rocket::async_test]
+    async fn rejects_escaping_symlinks() {
+        let f = fixture();
+        for path in ["escape.txt", "parent/secret.txt"] {
+            let result = resolve(&f.root, Path::new(path)).await;
+            assert_eq!(rejection(result), PathError::Escapes, "{}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[rocket::async_test]
+    async fn follows_contained_symlinks() {
+        let f = fixture();
+        let resolved = resolve(&f.root, Path::new("nested/alias.css"))
+            .await
+            .unwrap();
+        assert_eq!(resolved, f.root.join("style.css"));
+    }
+
+    #[rocket::async_test]
+    async fn missing_files_are_not_found() {
+        let f = fixture();
+        let e = resolve(&f.root, Path::new("missing.css"))
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+    }
+}
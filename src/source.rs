@@ -0,0 +1,486 @@
+//! Backends assets are read from: the local filesystem, an in-memory map, or any other
+//! [`AssetSource`] implementation.
+use crate::manifest::{self, url_path};
+use crate::resolve;
+use normpath::PathExt;
+use rocket::http::ContentType;
+use rocket::tokio::fs::File;
+use rocket::tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A streaming (and seekable) asset body, as returned by [`AssetSource::open()`]
+pub trait AssetReader: AsyncRead + AsyncSeek + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncSeek + Send + Unpin + ?Sized> AssetReader for T {}
+
+/// Metadata of an asset, as returned by [`AssetSource::metadata()`]
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    /// Size of the contents, in bytes
+    pub len: u64,
+    /// Last modification time, if known
+    pub modified: Option<SystemTime>,
+    /// Opaque identifier of the contents' version, changing whenever they do (used as the strong
+    /// `ETag`, so it shouldn't contain double quotes)
+    pub version: String,
+    /// Media type, guessed from the path's extension when `None`
+    pub content_type: Option<ContentType>,
+}
+
+/// A backend assets are read from
+///
+/// Paths given to sources are relative, normalized (without `.` or `..` segments) and use `/`
+/// as separator. Precompressed sidecars are looked up as `<path>.<extension>` (e.g.
+/// `style.css.br`) in the same source as the asset.
+///
+/// Sources are type erased, so any backend (archives, object stores, ...) can be used with
+/// [`Assets::fairing_from_source()`]. See [`FileSource`] and [`MemorySource`] for the built-in ones.
+///
+/// [`Assets::fairing_from_source()`]: crate::Assets::fairing_from_source
+#[rocket::async_trait]
+pub trait AssetSource: Send + Sync + 'static {
+    /// Looks up an asset, failing with [`io::ErrorKind::NotFound`] if it's missing (or isn't a
+    /// file)
+    async fn metadata(&self, path: &str) -> io::Result<Metadata>;
+
+    /// Opens an asset's contents
+    async fn open(&self, path: &str) -> io::Result<Box<dyn AssetReader>>;
+
+    /// Lists the paths of every asset, used for fingerprinting
+    async fn paths(&self) -> io::Result<Vec<String>>;
+
+    /// SHA-256 digest of an asset's contents, reading it whole by default
+    async fn digest(&self, path: &str) -> io::Result<[u8; 32]> {
+        let mut reader = self.open(path).await?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 8 * 1024];
+        loop {
+            match reader.read(&mut buffer).await? {
+                0 => return Ok(hasher.finalize().into()),
+                read => hasher.update(&buffer[..read]),
+            }
+        }
+    }
+
+    /// Directory the assets are stored in, if any
+    fn root(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Short description of the source, used when logging
+    fn describe(&self) -> String {
+        std::any::type_name::<Self>().to_string()
+    }
+}
+
+/// Reads an asset whole
+#[cfg(any(feature = "compress", feature = "tera", feature = "handlebars", test))]
+pub(crate) async fn read(source: &dyn AssetSource, path: &str) -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    source.open(path).await?.read_to_end(&mut contents).await?;
+    Ok(contents)
+}
+
+/// Assets stored in a local directory
+///
+/// Symlinks are followed, as long as they point inside of the directory.
+#[derive(Debug, Clone)]
+pub struct FileSource {
+    root: PathBuf,
+}
+
+impl FileSource {
+    /// Serves the assets in `root`, which is normalized (and must exist)
+    pub fn new<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+        let root = root.as_ref().normalize()?.into_path_buf();
+        Ok(FileSource { root })
+    }
+
+    async fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        resolve::resolve(&self.root, Path::new(path)).await
+    }
+}
+
+#[rocket::async_trait]
+impl AssetSource for FileSource {
+    async fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        let metadata = rocket::tokio::fs::metadata(self.resolve(path).await?).await?;
+        if !metadata.is_file() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        let modified = metadata.modified().ok();
+        let mtime = modified
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+        #[cfg(not(unix))]
+        let inode = 0u64;
+
+        Ok(Metadata {
+            len: metadata.len(),
+            modified,
+            version: format!("{:x}-{:x}-{:x}", inode, mtime.as_nanos(), metadata.len()),
+            content_type: None,
+        })
+    }
+
+    async fn open(&self, path: &str) -> io::Result<Box<dyn AssetReader>> {
+        Ok(Box::new(File::open(self.resolve(path).await?).await?))
+    }
+
+    async fn paths(&self) -> io::Result<Vec<String>> {
+        let root = self.root.clone();
+        let files = rocket::tokio::task::spawn_blocking(move || walk(&root)).await??;
+        Ok(files.into_iter().map(|(relative, _)| relative).collect())
+    }
+
+    fn root(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+
+    fn describe(&self) -> String {
+        self.root.display().to_string()
+    }
+}
+
+/// Recursively lists the files in `root`, as relative paths (with `/` separators) and the
+/// canonical path they point to
+pub(crate) fn walk(root: &Path) -> io::Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let file_type = fs::symlink_metadata(&path)?.file_type();
+
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() || file_type.is_symlink() {
+                // Symlinks are only followed to files still inside of the root
+                let target = match fs::canonicalize(&path) {
+                    Ok(target) if target.starts_with(root) && target.is_file() => target,
+                    _ => continue,
+                };
+                if let Some(relative) = path.strip_prefix(root).ok().and_then(url_path) {
+                    files.push((relative, target));
+                }
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+#[derive(Debug)]
+struct MemoryFile {
+    contents: Arc<[u8]>,
+    content_type: Option<ContentType>,
+    modified: SystemTime,
+    digest: [u8; 32],
+}
+
+/// Assets kept in memory, e.g. generated at startup or used as a stand-in in tests
+///
+/// ```rust
+/// use rocket_assets_fairing::{Assets, MemorySource};
+///
+/// let mut source = MemorySource::new();
+/// source.insert("robots.txt", "User-agent: *\nDisallow:\n");
+/// let fairing = Assets::fairing_from_source(source);
+/// ```
+#[derive(Debug, Default)]
+pub struct MemorySource {
+    files: HashMap<String, MemoryFile>,
+}
+
+impl MemorySource {
+    /// An empty source, to be filled with [`MemorySource::insert()`]
+    pub fn new() -> Self {
+        MemorySource::default()
+    }
+
+    /// Adds (or replaces) an asset, at a relative path with `/` separators
+    pub fn insert<P, C>(&mut self, path: P, contents: C) -> &mut Self
+    where
+        P: Into<String>,
+        C: Into<Vec<u8>>,
+    {
+        self.store(path.into(), contents.into(), None)
+    }
+
+    /// Adds (or replaces) an asset with an explicit media type
+    pub fn insert_typed<P, C>(
+        &mut self,
+        path: P,
+        content_type: ContentType,
+        contents: C,
+    ) -> &mut Self
+    where
+        P: Into<String>,
+        C: Into<Vec<u8>>,
+    {
+        self.store(path.into(), contents.into(), Some(content_type))
+    }
+
+    fn store(
+        &mut self,
+        path: String,
+        contents: Vec<u8>,
+        content_type: Option<ContentType>,
+    ) -> &mut Self {
+        let file = MemoryFile {
+            digest: Sha256::digest(&contents).into(),
+            contents: contents.into(),
+            content_type,
+            modified: SystemTime::now(),
+        };
+        self.files.insert(path, file);
+        self
+    }
+
+    fn get(&self, path: &str) -> io::Result<&MemoryFile> {
+        self.files
+            .get(path)
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+}
+
+#[rocket::async_trait]
+impl AssetSource for MemorySource {
+    async fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        let file = self.get(path)?;
+        Ok(Metadata {
+            len: file.contents.len() as u64,
+            modified: Some(file.modified),
+            version: manifest::hex(&file.digest[..16]),
+            content_type: file.content_type.clone(),
+        })
+    }
+
+    async fn open(&self, path: &str) -> io::Result<Box<dyn AssetReader>> {
+        Ok(Box::new(Cursor::new(self.get(path)?.contents.clone())))
+    }
+
+    async fn paths(&self) -> io::Result<Vec<String>> {
+        Ok(self.files.keys().cloned().collect())
+    }
+
+    async fn digest(&self, path: &str) -> io::Result<[u8; 32]> {
+        Ok(self.get(path)?.digest)
+    }
+
+    fn describe(&self) -> String {
+        format!("{} in-memory files", self.files.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Recording;
+    use crate::{Assets, AssetsConfig};
+    use rocket::http::{Header, Status};
+    use rocket::local::asynchronous::Client;
+
+    fn memory() -> MemorySource {
+        let mut source = MemorySource::new();
+        source
+            .insert("app.js", "console.log('hi');")
+            .insert("app.js.br", "not really brotli")
+            .insert_typed("data", ContentType::JSON, "{}");
+        source
+    }
+
+    async fn client(source: MemorySource) -> Client {
+        let rocket = rocket::build()
+            .attach(Assets::fairing_from_source(source))
+            .mount("/", Assets::routes());
+        Client::tracked(rocket).await.expect("valid rocket")
+    }
+
+    #[rocket::async_test]
+    async fn memory_source_serves_its_files() {
+        let source = memory();
+        let metadata = source.metadata("app.js").await.unwrap();
+        assert_eq!(metadata.len, 18);
+        assert_eq!(
+            read(&source, "app.js").await.unwrap(),
+            b"console.log('hi');"
+        );
+
+        let mut paths = source.paths().await.unwrap();
+        paths.sort();
+        assert_eq!(paths, ["app.js", "app.js.br", "data"]);
+
+        let error = source.metadata("missing.js").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn default_digest_reads_the_contents() {
+        let expected: [u8; 32] = Sha256::digest(b"console.log('hi');").into();
+        assert_eq!(memory().digest("app.js").await.unwrap(), expected);
+        let source = Recording::new(memory());
+        assert_eq!(source.digest("app.js").await.unwrap(), expected);
+        assert_eq!(source.opened(), ["app.js"]);
+    }
+
+    #[rocket::async_test]
+    async fn file_source_skips_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("css")).unwrap();
+        fs::write(dir.path().join("css/style.css"), "body {}").unwrap();
+
+        let source = FileSource::new(dir.path()).unwrap();
+        assert_eq!(source.metadata("css/style.css").await.unwrap().len, 7);
+        let error = source.metadata("css").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert_eq!(source.paths().await.unwrap(), ["css/style.css"]);
+    }
+
+    #[rocket::async_test]
+    async fn assets_are_served_from_custom_sources() {
+        let client = client(memory()).await;
+
+        let response = client.get("/app.js").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JavaScript));
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        assert_eq!(response.into_string().await.unwrap(), "console.log('hi');");
+
+        let response = client
+            .get("/app.js")
+            .header(Header::new("If-None-Match", etag))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotModified);
+
+        let response = client.get("/data").dispatch().await;
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+
+        let response = client.get("/missing.js").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn sidecars_and_fingerprints_come_from_the_source() {
+        let client = client(memory()).await;
+        let assets = client.rocket().state::<Assets>().unwrap();
+
+        let response = client
+            .get("/app.js")
+            .header(Header::new("Accept-Encoding", "br"))
+            .dispatch()
+            .await;
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("br"));
+        assert_eq!(response.into_string().await.unwrap(), "not really brotli");
+
+        let response = client
+            .get("/app.js")
+            .header(Header::new("Accept-Encoding", "br"))
+            .header(Header::new("Range", "bytes=4-9"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::PartialContent);
+        assert_eq!(response.into_string().await.unwrap(), "really");
+
+        let url = assets.url("app.js").unwrap();
+        assert_ne!(url, "/app.js");
+        let response = client.get(url).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let cache_control = response.headers().get_one("Cache-control").unwrap();
+        assert!(cache_control.contains("immutable"));
+
+        let error = assets.layer("app.js").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[rocket::async_test]
+    async fn earlier_layers_override_later_ones() {
+        let overrides = tempfile::tempdir().unwrap();
+        let base = tempfile::tempdir().unwrap();
+        fs::write(overrides.path().join("style.css"), "override").unwrap();
+        fs::write(base.path().join("style.css"), "base").unwrap();
+        fs::write(base.path().join("style.css.br"), "base brotli").unwrap();
+        fs::write(base.path().join("app.js"), "base app").unwrap();
+        fs::write(base.path().join("app.js.br"), "base app brotli").unwrap();
+        // Directories don't shadow files
+        fs::create_dir(overrides.path().join("data")).unwrap();
+        fs::write(base.path().join("data"), "base data").unwrap();
+
+        let config = AssetsConfig::new().dirs([overrides.path(), base.path()]);
+        let rocket = rocket::build()
+            .attach(Assets::fairing_with(config))
+            .mount("/", Assets::routes());
+        let client = Client::tracked(rocket).await.expect("valid rocket");
+        let assets = client.rocket().state::<Assets>().unwrap();
+        let get = |path: &str| {
+            let request = client.get(path.to_string());
+            request.header(Header::new("Accept-Encoding", "br"))
+        };
+
+        // Sidecars only come from the layer the file is served from
+        let response = get("/style.css").dispatch().await;
+        assert_eq!(response.headers().get_one("Content-Encoding"), None);
+        assert_eq!(response.into_string().await.unwrap(), "override");
+        let response = get("/app.js").dispatch().await;
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("br"));
+        assert_eq!(response.into_string().await.unwrap(), "base app brotli");
+        let response = get("/data").dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "base data");
+
+        let response = get(&assets.url("style.css").unwrap()).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "override");
+
+        assert_eq!(assets.layer("style.css").await.unwrap(), overrides.path());
+        assert_eq!(assets.layer("app.js").await.unwrap(), base.path());
+        assert_eq!(assets.layer("data").await.unwrap(), base.path());
+        let error = assets.layer("missing.js").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+        let manifest = assets.manifest();
+        assert_eq!(manifest.entry("style.css").unwrap().layers, [0, 1]);
+        assert_eq!(manifest.entry("app.js").unwrap().layers, [1]);
+    }
+
+    #[rocket::async_test]
+    async fn sidecars_are_only_opened_when_served() {
+        let source = Recording::new(memory());
+        let rocket = rocket::build()
+            .attach(Assets::fairing_from_source(source.clone()))
+            .mount("/", Assets::routes());
+        let client = Client::tracked(rocket).await.expect("valid rocket");
+        source.clear();
+
+        let response = client.get("/app.js").dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "console.log('hi');");
+        assert_eq!(source.opened(), ["app.js"]);
+        source.clear();
+
+        let response = client
+            .head("/app.js")
+            .header(Header::new("Accept-Encoding", "br"))
+            .dispatch()
+            .await;
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("br"));
+        assert_eq!(source.opened(), ["app.js"]);
+        source.clear();
+
+        let response = client
+            .get("/app.js")
+            .header(Header::new("Accept-Encoding", "br"))
+            .dispatch()
+            .await;
+        assert_eq!(response.into_string().await.unwrap(), "not really brotli");
+        assert_eq!(source.opened(), ["app.js", "app.js.br"]);
+    }
+}
@@ -0,0 +1,386 @@
+//! Helpers for `rocket_dyn_templates`' engines, resolving asset URLs in templates.
+use crate::collection::{self, Collections};
+use crate::error::AssetError;
+use crate::manifest::{self, Manifest};
+use crate::source::{self, AssetSource};
+use crate::{resolve, Assets};
+use rocket::fairing::{self, Fairing, Info, Kind};
+use rocket::tokio::runtime::{self, Handle, RuntimeFlavor};
+use rocket::tokio::task;
+use rocket::{error, Build, Phase, Rocket};
+use rocket_dyn_templates::{Engines, Template};
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+
+/// Callback customizing the engines, after the helpers are registered
+type Customize = Box<dyn Fn(&mut Engines) + Send + Sync + 'static>;
+
+/// The fairing attaching `rocket_dyn_templates`' one, with the asset helpers registered
+pub(crate) struct TemplatesFairing {
+    customize: Arc<Customize>,
+}
+
+impl TemplatesFairing {
+    pub fn new(customize: Customize) -> Self {
+        TemplatesFairing {
+            customize: Arc::new(customize),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for TemplatesFairing {
+    fn info(&self) -> Info {
+        Info {
+            kind: Kind::Ignite,
+            name: "Asset Templates",
+        }
+    }
+
+    async fn on_ignite(&self, rocket: Rocket<Build>) -> fairing::Result {
+        let helpers = match Helpers::new(&rocket) {
+            Some(helpers) => Arc::new(helpers),
+            None => {
+                error!("`Assets::templates()` attached before any `Assets` fairing.");
+                return Err(rocket);
+            }
+        };
+        let customize = self.customize.clone();
+        Ok(rocket.attach(Template::custom(move |engines| {
+            helpers.register(engines);
+            customize(engines);
+        })))
+    }
+}
+
+/// What the helpers need to know about a collection
+struct Helper {
+    manifest: Arc<RwLock<Manifest>>,
+    layers: Vec<Arc<dyn AssetSource>>,
+    /// Public URL of the collection, see [`Assets::url()`]
+    base: String,
+}
+
+impl Helper {
+    fn new(assets: &Assets) -> Self {
+        Helper {
+            manifest: assets.manifest.clone(),
+            layers: assets.layers.clone(),
+            base: assets.base.clone(),
+        }
+    }
+
+    fn manifest(&self) -> RwLockReadGuard<'_, Manifest> {
+        self.manifest.read().expect("manifest lock poisoned")
+    }
+}
+
+/// A helper function, taking the collection's name and the asset's path
+type Function = fn(&Helpers, Option<&str>, &str) -> Result<String, String>;
+
+/// The helpers registered in every engine
+const FUNCTIONS: [(&str, Function); 3] = [
+    ("asset_url", Helpers::url),
+    ("asset_integrity", Helpers::integrity),
+    ("asset_inline", Helpers::inline),
+];
+
+/// The loaded collections, shared by every helper
+struct Helpers {
+    default: Option<Helper>,
+    named: HashMap<String, Helper>,
+}
+
+impl Helpers {
+    fn new<P: Phase>(rocket: &Rocket<P>) -> Option<Self> {
+        let default = collection::find(rocket, None).map(Helper::new);
+        let named = rocket
+            .state::<Collections>()
+            .map(|collections| {
+                let loaded = collections.loaded();
+                loaded
+                    .map(|(name, assets)| (name.to_string(), Helper::new(assets)))
+                    .collect::<HashMap<_, _>>()
+            })
+            .unwrap_or_default();
+        match default.is_none() && named.is_empty() {
+            true => None,
+            false => Some(Helpers { default, named }),
+        }
+    }
+
+    fn collection(&self, name: Option<&str>) -> Result<&Helper, String> {
+        let helper = match name {
+            None => self.default.as_ref(),
+            Some(name) => self.named.get(name),
+        };
+        helper.ok_or_else(|| match name {
+            None => "The default asset collection isn't attached".to_string(),
+            Some(name) => format!("Asset collection '{}' isn't attached", name),
+        })
+    }
+
+    /// Fingerprinted URL of an asset, see [`Assets::url()`]
+    fn url(&self, name: Option<&str>, path: &str) -> Result<String, String> {
+        let helper = self.collection(name)?;
+        let relative = relative(path)?;
+        let manifest = helper.manifest();
+        let fingerprinted = manifest
+            .fingerprinted(&relative)
+            .ok_or_else(|| not_found(path))?;
+        Ok(manifest::public_url(&helper.base, fingerprinted))
+    }
+
+    fn integrity(&self, name: Option<&str>, path: &str) -> Result<String, String> {
+        let helper = self.collection(name)?;
+        let relative = relative(path)?;
+        let manifest = helper.manifest();
+        manifest
+            .fingerprinted(&relative)
+            .ok_or_else(|| not_found(path))?;
+        let integrity = manifest
+            .integrity(&relative)
+            .ok_or_else(|| format!("No integrity of '{}', `assets_integrity` is empty", path))?;
+        Ok(integrity.to_string())
+    }
+
+    /// Contents of a (text) asset, to be embedded in the page
+    fn inline(&self, name: Option<&str>, path: &str) -> Result<String, String> {
+        let helper = self.collection(name)?;
+        let relative = relative(path)?;
+        let layer = {
+            let manifest = helper.manifest();
+            manifest
+                .entry(&relative)
+                .ok_or_else(|| not_found(path))?
+                .layers[0]
+        };
+        let contents = read_blocking(&*helper.layers[layer], &relative)
+            .map_err(|e| AssetError::new(path.to_string(), e).to_string())?;
+        String::from_utf8(contents).map_err(|_| format!("Asset '{}' isn't UTF-8 text", path))
+    }
+
+    fn register(self: &Arc<Self>, engines: &mut Engines) {
+        #[cfg(feature = "tera")]
+        tera::register(self, &mut engines.tera);
+        #[cfg(feature = "handlebars")]
+        handlebars::register(self, &mut engines.handlebars);
+    }
+}
+
+/// Manifest key of a path given to a helper
+fn relative(path: &str) -> Result<String, String> {
+    let normalized = resolve::normalize(Path::new(path))
+        .map_err(|e| AssetError::new(path.to_string(), e.into()).to_string())?;
+    manifest::url_path(&normalized).ok_or_else(|| not_found(path))
+}
+
+fn not_found(path: &str) -> String {
+    let path = path.to_string();
+    AssetError::NotFound { path }.to_string()
+}
+
+/// Reads an asset from a (synchronous) helper, which may run within Rocket's runtime or not
+fn read_blocking(source: &dyn AssetSource, path: &str) -> io::Result<Vec<u8>> {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            task::block_in_place(|| handle.block_on(source::read(source, path)))
+        }
+        _ => std::thread::scope(|scope| {
+            let reading = scope.spawn(|| {
+                let runtime = runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                runtime.block_on(source::read(source, path))
+            });
+            reading.join().expect("asset reading thread panicked")
+        }),
+    }
+}
+
+#[cfg(feature = "tera")]
+mod tera {
+    use super::{Helpers, FUNCTIONS};
+    use rocket_dyn_templates::tera::{self, Tera, Value};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    type Args = HashMap<String, Value>;
+
+    /// Registers `asset_url(path=...)`, `asset_integrity(path=...)` and
+    /// `asset_inline(path=...)`, all taking an optional `collection` argument
+    pub fn register(helpers: &Arc<Helpers>, tera: &mut Tera) {
+        for (name, function) in FUNCTIONS {
+            let helpers = helpers.clone();
+            tera.register_function(name, move |args: &Args| {
+                let path = string(args, name, "path")?.ok_or_else(|| {
+                    tera::Error::msg(format!("`{}` requires a `path` argument", name))
+                })?;
+                let collection = string(args, name, "collection")?;
+                function(&helpers, collection, path)
+                    .map(Value::String)
+                    .map_err(tera::Error::msg)
+            });
+        }
+    }
+
+    fn string<'a>(args: &'a Args, function: &str, key: &str) -> tera::Result<Option<&'a str>> {
+        match args.get(key) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(_) => Err(tera::Error::msg(format!(
+                "`{}` argument of `{}` must be a string",
+                key, function
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "handlebars")]
+mod handlebars {
+    use super::{Function, Helpers, FUNCTIONS};
+    use rocket_dyn_templates::handlebars::{
+        Context, Handlebars, Helper, HelperDef, JsonValue, RenderContext, RenderError,
+        RenderErrorReason, ScopedJson,
+    };
+    use std::sync::Arc;
+
+    /// A helper calling one of [`Helpers`]' functions
+    struct AssetHelper {
+        helpers: Arc<Helpers>,
+        name: &'static str,
+        function: Function,
+    }
+
+    /// Registers `{{asset_url "..."}}`, `{{asset_integrity "..."}}` and `{{asset_inline "..."}}`,
+    /// all taking an optional `collection="..."` hash argument
+    pub fn register(helpers: &Arc<Helpers>, handlebars: &mut Handlebars<'static>) {
+        for (name, function) in FUNCTIONS {
+            let helper = AssetHelper {
+                helpers: helpers.clone(),
+                name,
+                function,
+            };
+            handlebars.register_helper(name, Box::new(helper));
+        }
+    }
+
+    impl HelperDef for AssetHelper {
+        fn call_inner<'reg: 'rc, 'rc>(
+            &self,
+            h: &Helper<'rc>,
+            _: &'reg Handlebars<'reg>,
+            _: &'rc Context,
+            _: &mut RenderContext<'reg, 'rc>,
+        ) -> Result<ScopedJson<'rc>, RenderError> {
+            let path = h
+                .param(0)
+                .ok_or(RenderErrorReason::ParamNotFoundForIndex(self.name, 0))?;
+            let path = path
+                .value()
+                .as_str()
+                .ok_or(RenderErrorReason::InvalidParamType("string"))?;
+            let collection = match h.hash_get("collection") {
+                None => None,
+                Some(collection) => Some(
+                    collection
+                        .value()
+                        .as_str()
+                        .ok_or(RenderErrorReason::InvalidParamType("string"))?,
+                ),
+            };
+            let value = (self.function)(&self.helpers, collection, path)
+                .map_err(RenderErrorReason::Other)?;
+            Ok(ScopedJson::Derived(JsonValue::String(value)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Assets, MemorySource};
+    use rocket::http::Status;
+    use rocket::local::blocking::Client;
+    use rocket_dyn_templates::{context, Template};
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[rocket::get("/<name>")]
+    fn page(name: &str) -> Template {
+        Template::render(name.to_string(), context! {})
+    }
+
+    fn client(templates: &[(&str, &str)]) -> (Client, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, contents) in templates {
+            fs::write(dir.path().join(name), contents).unwrap();
+        }
+        let mut source = MemorySource::new();
+        source.insert("style.css", "body { color: red; }");
+        let figment = rocket::Config::figment()
+            .merge(("template_dir", dir.path()))
+            .merge(("assets_integrity", ["sha256"]));
+        let rocket = rocket::custom(figment)
+            .attach(Assets::fairing_from_source(source))
+            .attach(Assets::templates())
+            .mount("/static", Assets::routes())
+            .mount("/", rocket::routes![page]);
+        (Client::tracked(rocket).unwrap(), dir)
+    }
+
+    #[cfg(feature = "tera")]
+    #[test]
+    fn tera_functions_resolve_assets() {
+        let (client, _dir) = client(&[
+            (
+                "page.html.tera",
+                r#"{{ asset_url(path="style.css") | safe }} {{ asset_integrity(path="style.css") | safe }} {{ asset_inline(path="style.css") | safe }}"#,
+            ),
+            ("missing.html.tera", r#"{{ asset_url(path="app.js") }}"#),
+        ]);
+        let body = client.get("/page").dispatch().into_string().unwrap();
+        let mut parts = body.splitn(3, ' ');
+        let url = parts.next().unwrap();
+        assert!(
+            url.starts_with("/static/style.") && url.ends_with(".css"),
+            "{}",
+            url
+        );
+        assert!(parts.next().unwrap().starts_with("sha256-"));
+        assert_eq!(parts.next().unwrap(), "body { color: red; }");
+
+        let response = client.get("/missing").dispatch();
+        assert_eq!(response.status(), Status::InternalServerError);
+    }
+
+    #[cfg(feature = "handlebars")]
+    #[test]
+    fn handlebars_helpers_resolve_assets() {
+        let (client, _dir) = client(&[
+            (
+                "page.html.hbs",
+                r#"{{asset_url "style.css"}} {{{asset_inline "style.css"}}}"#,
+            ),
+            ("missing.html.hbs", r#"{{asset_url "app.js"}}"#),
+            (
+                "unknown.html.hbs",
+                r#"{{asset_url "style.css" collection="vendor"}}"#,
+            ),
+        ]);
+        let body = client.get("/page").dispatch().into_string().unwrap();
+        let (url, inline) = body.split_once(' ').unwrap();
+        assert!(
+            url.starts_with("/static/style.") && url.ends_with(".css"),
+            "{}",
+            url
+        );
+        assert_eq!(inline, "body { color: red; }");
+
+        let response = client.get("/missing").dispatch();
+        assert_eq!(response.status(), Status::InternalServerError);
+        let response = client.get("/unknown").dispatch();
+        assert_eq!(response.status(), Status::InternalServerError);
+    }
+}
@@ -0,0 +1,47 @@
+//! Helpers shared by the tests of several modules.
+use crate::source::{AssetReader, AssetSource, MemorySource, Metadata};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// A [`MemorySource`] recording the paths of the assets opened through it
+///
+/// It relies on the trait's default `digest()`, reading the assets through `open()`. Clones share
+/// their contents and recorded paths, so one can be attached while another is inspected.
+#[derive(Clone)]
+pub(crate) struct Recording {
+    source: Arc<MemorySource>,
+    opened: Arc<Mutex<Vec<String>>>,
+}
+
+impl Recording {
+    pub fn new(source: MemorySource) -> Self {
+        Recording {
+            source: Arc::new(source),
+            opened: Arc::default(),
+        }
+    }
+
+    /// The paths opened so far, in order
+    pub fn opened(&self) -> Vec<String> {
+        self.opened.lock().unwrap().clone()
+    }
+
+    /// Forgets the paths opened so far
+    pub fn clear(&self) {
+        self.opened.lock().unwrap().clear();
+    }
+}
+
+#[rocket::async_trait]
+impl AssetSource for Recording {
+    async fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        self.source.metadata(path).await
+    }
+    async fn open(&self, path: &str) -> io::Result<Box<dyn AssetReader>> {
+        self.opened.lock().unwrap().push(path.to_string());
+        self.source.open(path).await
+    }
+    async fn paths(&self) -> io::Result<Vec<String>> {
+        self.source.paths().await
+    }
+}
@@ -0,0 +1,256 @@
+//! Restricting the types of served assets, and neutralizing the ones able to run scripts.
+use crate::asset::Asset;
+use rocket::http::{ContentType, Header};
+use rocket::serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::path::Path;
+
+/// Policy sandboxing documents, so that they can't run scripts (nor load anything) on our origin
+const SANDBOX_POLICY: &str =
+    "sandbox; default-src 'none'; img-src 'self' data:; style-src 'unsafe-inline'";
+
+/// The `assets_types` configuration table
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(crate = "rocket::serde", default)]
+pub(crate) struct TypesConfig {
+    /// Extensions and media types allowed to be served, `None` allowing every type
+    pub allow: Option<Vec<TypePattern>>,
+    /// What to do with assets of other types
+    pub disallowed: Disallowed,
+    /// Whether to sandbox documents able to run scripts (HTML, SVG and XML)
+    pub sandbox: bool,
+}
+
+/// How assets whose type isn't allowed are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(crate = "rocket::serde", rename_all = "lowercase")]
+pub(crate) enum Disallowed {
+    /// Refused, as forbidden
+    #[default]
+    Refuse,
+    /// Served as an opaque download (`application/octet-stream`, as an attachment)
+    Download,
+}
+
+impl TypesConfig {
+    /// Applies the policy to an asset, returning `None` if it's refused
+    pub fn apply(&self, path: &str, mut asset: Asset) -> Option<Asset> {
+        let content_type = asset.identity.content_type.as_ref();
+        let allowed = self.allow.as_ref().is_none_or(|allow| {
+            allow
+                .iter()
+                .any(|pattern| pattern.matches(path, content_type))
+        });
+
+        if !allowed {
+            match self.disallowed {
+                Disallowed::Refuse => return None,
+                Disallowed::Download => {
+                    asset.identity.content_type = Some(ContentType::Binary);
+                    asset.headers.push(attachment(path));
+                    asset.headers.push(nosniff());
+                }
+            }
+        } else {
+            let headers = self.security_headers(content_type);
+            asset.headers.extend(headers);
+        }
+        Some(asset)
+    }
+
+    /// Headers sandboxing documents of a (served) type, if enabled
+    pub fn security_headers(&self, content_type: Option<&ContentType>) -> Vec<Header<'static>> {
+        match self.sandbox && content_type.is_some_and(is_scriptable) {
+            true => vec![
+                Header::new("Content-Security-Policy", SANDBOX_POLICY),
+                nosniff(),
+            ],
+            false => Vec::new(),
+        }
+    }
+}
+
+/// Whether browsers may run scripts embedded in documents of this type
+fn is_scriptable(content_type: &ContentType) -> bool {
+    match (content_type.top().as_str(), content_type.sub().as_str()) {
+        ("text", "html") | ("text", "xml") | ("image", "svg+xml") => true,
+        ("application", sub) => sub == "xml" || sub == "xhtml+xml",
+        _ => false,
+    }
+}
+
+fn nosniff() -> Header<'static> {
+    Header::new("X-Content-Type-Options", "nosniff")
+}
+
+/// `Content-Disposition` header downloading an asset under its own name
+fn attachment(path: &str) -> Header<'static> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    // Names needing escaping are left out, browsers then use the URL's
+    let quotable = name
+        .chars()
+        .all(|c| (c.is_ascii_graphic() || c == ' ') && c != '"' && c != '\\');
+    match quotable {
+        true => Header::new(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", name),
+        ),
+        false => Header::new("Content-Disposition", "attachment"),
+    }
+}
+
+/// An allowed type: either an extension (`png`) or a media type (`image/png`, `image/*`)
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct TypePattern(String);
+
+impl TypePattern {
+    pub fn new(pattern: &str) -> Self {
+        TypePattern(pattern.trim_start_matches('.').to_ascii_lowercase())
+    }
+
+    pub fn matches(&self, path: &str, content_type: Option<&ContentType>) -> bool {
+        match self.0.split_once('/') {
+            Some((top, sub)) => content_type.is_some_and(|content_type| {
+                top.eq_ignore_ascii_case(content_type.top().as_str())
+                    && (sub == "*" || sub.eq_ignore_ascii_case(content_type.sub().as_str()))
+            }),
+            None => Path::new(path)
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case(&self.0)),
+        }
+    }
+}
+
+impl fmt::Debug for TypePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for TypePattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for TypePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|pattern| TypePattern::new(&pattern))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AssetError, Assets, MemorySource};
+    use rocket::figment::providers::{Format, Toml};
+    use rocket::http::Status;
+    use rocket::local::asynchronous::Client;
+
+    async fn client(types: &str) -> Client {
+        let config = Toml::string(&format!("[default]\nassets_types = {}", types));
+        let figment = rocket::Config::figment().merge(config.nested());
+        let mut source = MemorySource::new();
+        source
+            .insert("style.css", "body {}")
+            .insert("app.js", "main()")
+            .insert("page.html", "<script></script>")
+            .insert("logo.svg", "<svg></svg>");
+        let rocket = rocket::custom(figment)
+            .attach(Assets::fairing_from_source(source))
+            .mount("/", Assets::routes());
+        Client::tracked(rocket).await.expect("valid rocket")
+    }
+
+    #[test]
+    fn patterns_match_extensions_and_media_types() {
+        let png = Some(&ContentType::PNG);
+        assert!(TypePattern::new("png").matches("a/b.PNG", png));
+        assert!(TypePattern::new(".png").matches("b.png", None));
+        assert!(TypePattern::new("image/png").matches("b", png));
+        assert!(TypePattern::new("image/*").matches("b.png", png));
+        assert!(!TypePattern::new("image/*").matches("b.png", None));
+        assert!(!TypePattern::new("png").matches("png", png));
+        assert!(!TypePattern::new("text/*").matches("b.png", png));
+    }
+
+    #[test]
+    fn scripting_documents_are_detected() {
+        for content_type in [ContentType::HTML, ContentType::SVG, ContentType::XML] {
+            assert!(is_scriptable(&content_type), "{}", content_type);
+        }
+        for content_type in [ContentType::PNG, ContentType::CSS, ContentType::Plain] {
+            assert!(!is_scriptable(&content_type), "{}", content_type);
+        }
+    }
+
+    #[test]
+    fn attachments_keep_safe_names() {
+        assert_eq!(
+            attachment("uploads/report 1.pdf").value(),
+            "attachment; filename=\"report 1.pdf\""
+        );
+        assert_eq!(attachment("a\"b.html").value(), "attachment");
+        assert_eq!(attachment("résumé.pdf").value(), "attachment");
+    }
+
+    #[rocket::async_test]
+    async fn disallowed_types_are_refused() {
+        let client = client(r#"{ allow = ["css", "image/*"] }"#).await;
+        assert_eq!(
+            client.get("/style.css").dispatch().await.status(),
+            Status::Ok
+        );
+        assert_eq!(
+            client.get("/logo.svg").dispatch().await.status(),
+            Status::Ok
+        );
+        let response = client.get("/app.js").dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let assets = client.rocket().state::<Assets>().unwrap();
+        let error = assets.open("app.js").await.err().unwrap();
+        assert!(matches!(error, AssetError::Disallowed { .. }), "{}", error);
+    }
+
+    #[rocket::async_test]
+    async fn disallowed_types_can_be_downloaded() {
+        let client = client(r#"{ allow = ["css"], disallowed = "download" }"#).await;
+        let response = client.get("/app.js").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::Binary));
+        let headers = response.headers();
+        assert_eq!(
+            headers.get_one("Content-Disposition"),
+            Some("attachment; filename=\"app.js\"")
+        );
+        assert_eq!(headers.get_one("X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(response.into_string().await.unwrap(), "main()");
+
+        let response = client.get("/style.css").dispatch().await;
+        assert_eq!(response.content_type(), Some(ContentType::CSS));
+        assert!(!response.headers().contains("Content-Disposition"));
+    }
+
+    #[rocket::async_test]
+    async fn scriptable_documents_are_sandboxed() {
+        let client = client("{ sandbox = true }").await;
+        for path in ["/page.html", "/logo.svg"] {
+            let response = client.get(path).dispatch().await;
+            let headers = response.headers();
+            assert_eq!(
+                headers.get_one("Content-Security-Policy"),
+                Some(SANDBOX_POLICY),
+                "{}",
+                path
+            );
+            assert_eq!(headers.get_one("X-Content-Type-Options"), Some("nosniff"));
+        }
+        let response = client.get("/style.css").dispatch().await;
+        assert!(!response.headers().contains("Content-Security-Policy"));
+
+        let client = self::client("{}").await;
+        let response = client.get("/page.html").dispatch().await;
+        assert!(!response.headers().contains("Content-Security-Policy"));
+    }
+}
@@ -0,0 +1,188 @@
+//! Hot reloading: watching the assets directories and invalidating derived state on changes.
+#[cfg(feature = "compress")]
+use crate::compression::Compressor;
+use crate::filter::Filter;
+use crate::integrity::HashAlgorithm;
+use crate::live_reload::Change;
+use crate::manifest::{self, Manifest};
+use crate::source::AssetSource;
+use crate::Assets;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use rocket::log::PaintExt;
+use rocket::tokio::sync::{broadcast, mpsc};
+use rocket::tokio::time::{sleep, Duration};
+use rocket::yansi::Paint;
+use rocket::{error, info, warn};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// How long to wait for more events before reloading, as editors usually write in bursts
+const DEBOUNCE: Duration = Duration::from_millis(100);
+/// Starts watching every directory of the collection, invalidating its manifest (hashes and
+/// fingerprints) and compression cache whenever files change
+///
+/// Entity tags are derived from the files' metadata when opening them, so they're always fresh.
+pub(crate) fn spawn(assets: &Assets) -> notify::Result<()> {
+    let roots = assets
+        .layers
+        .iter()
+        .filter_map(|source| source.root())
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    if roots.is_empty() {
+        return Ok(());
+    }
+
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = sender.send(event);
+    })?;
+    for root in &roots {
+        watcher.watch(root, RecursiveMode::Recursive)?;
+    }
+
+    let reloader = Reloader {
+        name: assets.name.clone(),
+        roots,
+        layers: assets.layers.clone(),
+        filter: assets.filter.clone(),
+        integrity: assets.integrity.clone(),
+        manifest: assets.manifest.clone(),
+        #[cfg(feature = "compress")]
+        compressor: assets.compressor.clone(),
+        changes: assets.changes.clone(),
+    };
+    rocket::tokio::spawn(async move {
+        // Keep watching for as long as the task runs
+        let _watcher = watcher;
+        while let Some(event) = events.recv().await {
+            let mut changed = BTreeSet::new();
+            reloader.collect(event, &mut changed);
+            sleep(DEBOUNCE).await;
+            while let Ok(event) = events.try_recv() {
+                reloader.collect(event, &mut changed);
+            }
+
+            if !changed.is_empty() {
+                reloader.reload(changed).await;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// The state shared with a collection, invalidated on changes
+struct Reloader {
+    name: Option<String>,
+    roots: Vec<PathBuf>,
+    layers: Vec<Arc<dyn AssetSource>>,
+    filter: Filter,
+    integrity: Vec<HashAlgorithm>,
+    manifest: Arc<RwLock<Manifest>>,
+    #[cfg(feature = "compress")]
+    compressor: Option<Arc<Compressor>>,
+    changes: broadcast::Sender<Change>,
+}
+
+impl Reloader {
+    /// Adds the (relative) paths affected by an event, leaving out hidden ones
+    fn collect(&self, event: notify::Result<Event>, changed: &mut BTreeSet<String>) {
+        let event = match event {
+            Ok(event) => event,
+            Err(e) => {
+                warn!("Error watching assets: {}.", e);
+                return;
+            }
+        };
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+
+        for path in &event.paths {
+            let relative = self
+                .roots
+                .iter()
+                .find_map(|root| path.strip_prefix(root).ok())
+                .and_then(manifest::url_path);
+            changed.extend(
+                relative.filter(|relative| !relative.is_empty() && self.filter.allows(relative)),
+            );
+        }
+    }
+
+    async fn reload(&self, changed: BTreeSet<String>) {
+        #[cfg(feature = "compress")]
+        if let Some(compressor) = &self.compressor {
+            for path in &changed {
+                compressor.invalidate(path);
+            }
+        }
+
+        match Manifest::build(&self.layers, &self.filter, &self.integrity).await {
+            Ok(manifest) => *self.manifest.write().expect("manifest lock poisoned") = manifest,
+            Err(e) => error!("Failed to fingerprint reloaded assets: {}.", e),
+        }
+
+        let changed = changed.into_iter().collect::<Vec<_>>();
+        {
+            let manifest = self.manifest.read().expect("manifest lock poisoned");
+            for path in &changed {
+                let fingerprinted = manifest.fingerprinted(path).map(String::from);
+                let path = path.clone();
+                // Failing just means nobody is listening
+                let _ = self.changes.send(Change {
+                    path,
+                    fingerprinted,
+                });
+            }
+        }
+        let header = match &self.name {
+            None => "Assets changed".to_string(),
+            Some(name) => format!("Assets changed ({})", name),
+        };
+        info!(
+            "{}{}: {}",
+            "📐 ".emoji(),
+            header.magenta(),
+            changed.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Assets, AssetsConfig};
+    use rocket::local::asynchronous::Client;
+    use rocket::tokio::time::{sleep, Duration, Instant};
+    use std::fs;
+
+    #[rocket::async_test]
+    async fn changes_refresh_fingerprints() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("app.js"), "console.log(1);").unwrap();
+
+        let config = AssetsConfig::new().dir(dir.path()).reload(true);
+        let rocket = rocket::build().attach(Assets::fairing_with(config));
+        let client = Client::tracked(rocket).await.expect("valid rocket");
+        let assets = client.rocket().state::<Assets>().unwrap();
+        let url = assets.url("app.js").unwrap();
+        let integrity = assets.integrity("app.js").unwrap();
+        assert_eq!(assets.url("new.js"), None);
+
+        fs::write(dir.path().join("app.js"), "console.log(2);").unwrap();
+        fs::write(dir.path().join("new.js"), "console.log(3);").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while assets.url("app.js").as_ref() == Some(&url) || assets.url("new.js").is_none() {
+            assert!(Instant::now() < deadline, "changes weren't picked up");
+            sleep(Duration::from_millis(50)).await;
+        }
+        assert_ne!(assets.integrity("app.js").unwrap(), integrity);
+
+        fs::remove_file(dir.path().join("new.js")).unwrap();
+        while assets.url("new.js").is_some() {
+            assert!(Instant::now() < deadline, "removal wasn't picked up");
+            sleep(Duration::from_millis(50)).await;
+        }
+    }
+}